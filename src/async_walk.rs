@@ -0,0 +1,166 @@
+//! Walks for async code running on a Tokio runtime, behind the `async` feature.
+//!
+//! The filesystem is only ever touched from Tokio's blocking thread pool, so the
+//! executor threads keep running other tasks meanwhile. Dropping a [`WalkStream`],
+//! or the future returned by [`collect`] or [`find`], cancels the walk behind it.
+//!
+//! All of these must be called from within a Tokio runtime.
+//!
+//! ```no_run
+//! # async fn run() {
+//! use fsx::{WalkEvent, WalkOptions, async_walk, filter::GitIgnoreFilter};
+//! use std::path::Path;
+//! use tokio_stream::StreamExt;
+//!
+//! let filter = GitIgnoreFilter::new(Path::new("."), &[]);
+//! let mut entries = async_walk::walk(".", filter, WalkOptions::default());
+//! while let Some(entry) = entries.next().await {
+//!     match entry {
+//!         Ok(entry) if entry.event() == WalkEvent::File => {
+//!             println!("{}", entry.path().display())
+//!         }
+//!         Ok(_) => {}
+//!         Err(e) => eprintln!("{e}"),
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::collect_stats::{FsStatsReport, collect_with_options};
+use crate::error::FsError;
+use crate::filter::PathFilter;
+use crate::find::{FindReport, find_with_options};
+use crate::walk::{CancelToken, PartialReason, WalkEntry, WalkOptions, Walker};
+use std::{
+    panic,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+use tokio::{sync::mpsc, task};
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+
+// How many entries the walk may get ahead of the consumer of a `WalkStream`
+const STREAM_BUFFER: usize = 256;
+
+/// The entries of a walk, in the order a [`Walker`] yields them. Returned by [`walk`].
+///
+/// The walk runs ahead of the consumer by a bounded number of entries. Dropping the
+/// stream stops it.
+pub struct WalkStream {
+    entries: ReceiverStream<Result<WalkEntry, FsError>>,
+    partial: Arc<OnceLock<PartialReason>>,
+    _cancel: CancelOnDrop,
+}
+
+impl WalkStream {
+    /// Why the walk ended early, if one of the limits in the [`WalkOptions`] was hit.
+    /// Only known once the stream has ended.
+    pub fn partial(&self) -> Option<PartialReason> {
+        self.partial.get().copied()
+    }
+}
+
+impl Stream for WalkStream {
+    type Item = Result<WalkEntry, FsError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.entries).poll_next(cx)
+    }
+}
+
+/// Walk `root` like a [`Walker`] on the blocking thread pool, yielding its entries
+/// as a stream.
+///
+/// Directories can't be skipped from the stream, use the filter or
+/// [`WalkOptions::max_depth`] to leave them out.
+pub fn walk<F>(root: impl Into<PathBuf>, filter: F, mut options: WalkOptions) -> WalkStream
+where
+    F: PathFilter + Send + 'static,
+{
+    let root = root.into();
+    let cancel = CancelOnDrop::new(&mut options);
+    let partial = Arc::new(OnceLock::new());
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+
+    let walk_partial = Arc::clone(&partial);
+    task::spawn_blocking(move || {
+        let mut walker = Walker::new(&root, &filter, &options);
+        for entry in walker.by_ref() {
+            // The stream was dropped
+            if sender.blocking_send(entry).is_err() {
+                return;
+            }
+        }
+        if let Some(reason) = walker.partial() {
+            let _ = walk_partial.set(reason);
+        }
+    });
+
+    WalkStream {
+        entries: ReceiverStream::new(receiver),
+        partial,
+        _cancel: cancel,
+    }
+}
+
+/// [`collect_with_options`] on the blocking thread pool
+pub async fn collect<F>(
+    root: impl Into<PathBuf>,
+    filter: F,
+    mut options: WalkOptions,
+) -> FsStatsReport
+where
+    F: PathFilter + Send + 'static,
+{
+    let root = root.into();
+    let _cancel = CancelOnDrop::new(&mut options);
+    unblock(move || collect_with_options(&root, &filter, &options)).await
+}
+
+/// [`find_with_options`] on the blocking thread pool
+pub async fn find<F>(
+    root: impl Into<PathBuf>,
+    filter: F,
+    pattern: impl Into<String>,
+    mut options: WalkOptions,
+) -> FindReport
+where
+    F: PathFilter + Send + 'static,
+{
+    let root = root.into();
+    let pattern = pattern.into();
+    let _cancel = CancelOnDrop::new(&mut options);
+    unblock(move || find_with_options(&root, &filter, &pattern, &options)).await
+}
+
+// Run `f` on the blocking thread pool, passing on its panics
+async fn unblock<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// Cancels a walk when the stream or future driving it is dropped. The walk gets a
+// child of the token already in the options, so that the caller can still cancel
+// it while dropping it doesn't cancel other walks sharing that token.
+struct CancelOnDrop(CancelToken);
+
+impl CancelOnDrop {
+    fn new(options: &mut WalkOptions) -> Self {
+        let token = match &options.cancel {
+            Some(token) => token.child(),
+            None => CancelToken::new(),
+        };
+        options.cancel = Some(token.clone());
+        Self(token)
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
@@ -1,5 +1,11 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use fsx::filter::IgnoreOptions;
+use fsx::{
+    ErrorPolicy, FindType, ProgressReporter, SortBy, SpecialKind, WalkOptions, WalkStrategy,
+};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use crate::output;
 
@@ -14,50 +20,285 @@ pub struct Cli {
 pub enum Commands {
     /// Compute filesystem statistics for a directory tree
     Stats {
-        /// root directory to analyze
-        #[arg(default_value = ".")]
-        path: PathBuf,
-        /// Limit recursion to a maximum depth
-        ///
-        /// Depth starts at 1 for entries directly under PATH
-        ///
-        /// If not set, the entire directory tree is traversed.
-        #[arg(short, long)]
-        max_depth: Option<usize>,
+        /// root directories to analyze. With several roots, each gets its own
+        /// statistics followed by a total
+        #[arg(value_name = "PATH", default_value = ".")]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        walk: WalkArgs,
+        #[command(flatten)]
+        errors: ErrorArgs,
         /// Output format: human (default), raw (bytes), debug (Rust struct)
         #[arg(long, default_value = "human")]
         format: output::OutputFormat,
-        /// Recurse into symbolic links
-        #[arg(long)]
-        follow_symlinks: bool,
         /// Ignore filter (gitignore semantics)
         #[arg(short, long)]
         ignore: Option<Vec<String>>,
+        #[command(flatten)]
+        ignore_sources: IgnoreArgs,
+        /// Also list the files whose name matches this regex, found in the same walk
+        #[arg(long, value_name = "REGEX")]
+        list_matches: Option<String>,
     },
 
     /// Find matching nodes in the directory tree
     Find {
-        /// root directory to analyze
-        #[arg(default_value = ".")]
-        path: PathBuf,
+        /// root directories to search
+        #[arg(value_name = "PATH", default_value = ".")]
+        paths: Vec<PathBuf>,
         /// Regex pattern for file names
         #[arg(short, long)]
         regex: Option<String>,
-        /// Limit recursion to a maximum depth
-        ///
-        /// Depth starts at 1 for entries directly under PATH
-        ///
-        /// If not set, the entire directory tree is traversed.
-        #[arg(short, long)]
-        max_depth: Option<usize>,
+        /// Kind of entries to look for
+        #[arg(long = "type", default_value = "f")]
+        entry_type: TypeArg,
+        /// List symlinks whose target does not exist instead of files
+        #[arg(long, conflicts_with = "entry_type")]
+        broken_symlinks: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+        #[command(flatten)]
+        errors: ErrorArgs,
         /// Output format: human (default), raw (bytes), debug (Rust struct)
         #[arg(long, default_value = "human")]
         format: output::OutputFormat,
-        /// Recurse into symbolic links
-        #[arg(long)]
-        follow_symlinks: bool,
         /// Ignore filter (gitignore semantics)
         #[arg(short, long)]
         ignore: Option<Vec<String>>,
+        #[command(flatten)]
+        ignore_sources: IgnoreArgs,
     },
 }
+
+/// Options controlling the traversal, shared by all commands
+#[derive(Args)]
+pub struct WalkArgs {
+    /// Limit recursion to a maximum depth
+    ///
+    /// Depth starts at 1 for entries directly under PATH
+    ///
+    /// If not set, the entire directory tree is traversed.
+    #[arg(short, long)]
+    max_depth: Option<usize>,
+    /// Only report entries at this depth or deeper
+    ///
+    /// Shallower directories are still walked, their entries are just left out.
+    #[arg(long)]
+    min_depth: Option<usize>,
+    /// Recurse into symbolic links
+    #[arg(long)]
+    follow_symlinks: bool,
+    /// Report entries below followed symlinks by their path through the link
+    /// instead of the resolved path, and apply ignore rules to that path
+    #[arg(long, requires = "follow_symlinks")]
+    logical_paths: bool,
+    /// Number of threads to walk with. 0 uses all available cores
+    #[arg(short = 'j', long, default_value_t = 1)]
+    threads: usize,
+    /// Order in which the entries of each directory are visited
+    #[arg(long, default_value = "none")]
+    sort: SortKey,
+    /// Reverse the sort order
+    #[arg(long)]
+    reverse: bool,
+    /// Visit everything at one depth before going deeper
+    #[arg(long)]
+    breadth_first: bool,
+    /// Don't descend into directories on other filesystems
+    #[arg(long)]
+    one_file_system: bool,
+    /// Stop after this many entries and report what was found so far
+    #[arg(long)]
+    max_entries: Option<usize>,
+    /// Stop after this many seconds and report what was found so far
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Show progress on stderr while walking. `auto` only does so when stderr is a
+    /// terminal
+    #[arg(long, default_value = "auto")]
+    progress: ProgressWhen,
+    /// What to do when an error occurs
+    #[arg(long, value_name = "POLICY", default_value = "collect")]
+    on_error: OnError,
+    /// Walk through directory file descriptors instead of full paths. Linux only
+    #[arg(long)]
+    dir_fds: bool,
+}
+
+/// Options controlling how errors are printed
+#[derive(Args)]
+pub struct ErrorArgs {
+    /// Only print the number of errors of every kind, not the errors themselves
+    #[arg(long)]
+    pub quiet_errors: bool,
+    /// Print at most this many errors, followed by the number of errors of every kind
+    #[arg(long, value_name = "N")]
+    pub max_errors: Option<usize>,
+}
+
+/// Options controlling which ignore files are applied, shared by all commands
+#[derive(Args)]
+pub struct IgnoreArgs {
+    /// Apply the gitignore patterns in this file. Can be repeated
+    ///
+    /// A relative FILE is relative to the working directory, the patterns in it are
+    /// relative to PATH. The command fails if FILE can't be read.
+    #[arg(long, value_name = "FILE")]
+    ignore_file: Vec<PathBuf>,
+    /// Leave out the paths listed in this file, one per line. Can be repeated
+    ///
+    /// A relative FILE is relative to the working directory, relative paths in it
+    /// are relative to PATH. No pattern syntax is recognised. The command fails if
+    /// FILE can't be read.
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+    /// Don't apply .fsxignore, .ignore and .gitignore files or git's excludes files
+    ///
+    /// Patterns given with --ignore, --ignore-file and --exclude-from still apply.
+    #[arg(long)]
+    no_ignore: bool,
+    /// Don't apply .gitignore files or git's excludes files
+    #[arg(long)]
+    no_vcs_ignore: bool,
+    /// Don't apply the global git excludes file (core.excludesFile)
+    #[arg(long)]
+    no_global_ignore: bool,
+}
+
+impl IgnoreArgs {
+    pub fn to_options(&self) -> IgnoreOptions {
+        IgnoreOptions {
+            automatic: !self.no_ignore,
+            vcs_ignore: !self.no_vcs_ignore,
+            global_ignore: !self.no_global_ignore,
+            ignore_files: self.ignore_file.iter().map(|file| absolute(file)).collect(),
+            exclude_from: self
+                .exclude_from
+                .iter()
+                .map(|file| absolute(file))
+                .collect(),
+        }
+    }
+}
+
+// Like every other path on the command line, and unlike in `IgnoreOptions`, files
+// are relative to the working directory
+fn absolute(file: &Path) -> PathBuf {
+    std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf())
+}
+
+impl WalkArgs {
+    pub fn to_options(&self) -> WalkOptions {
+        WalkOptions {
+            max_depth: self.max_depth,
+            min_depth: self.min_depth,
+            follow_symlinks: self.follow_symlinks,
+            logical_paths: self.logical_paths,
+            threads: self.threads,
+            sort: self.sort.into(),
+            sort_reverse: self.reverse,
+            strategy: if self.breadth_first {
+                WalkStrategy::BreadthFirst
+            } else {
+                WalkStrategy::DepthFirst
+            },
+            one_file_system: self.one_file_system,
+            cancel: None,
+            max_entries: self.max_entries,
+            deadline: self
+                .timeout
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+            progress: self.progress_reporter(),
+            error_policy: self.on_error.into(),
+            dir_fds: self.dir_fds,
+        }
+    }
+
+    fn progress_reporter(&self) -> Option<ProgressReporter> {
+        let tty = std::io::stderr().is_terminal();
+        match self.progress {
+            ProgressWhen::Never => None,
+            ProgressWhen::Auto if !tty => None,
+            _ => Some(output::progress_reporter(tty)),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ProgressWhen {
+    /// When stderr is a terminal
+    Auto,
+    /// Always, as a line every few seconds when stderr is not a terminal
+    Always,
+    /// Never
+    Never,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum OnError {
+    /// Don't print errors and keep going
+    Ignore,
+    /// Print all errors after the results
+    Collect,
+    /// Stop at the first error and report what was found so far
+    FailFast,
+}
+
+impl From<OnError> for ErrorPolicy {
+    fn from(on_error: OnError) -> Self {
+        match on_error {
+            OnError::Ignore => ErrorPolicy::Ignore,
+            OnError::Collect => ErrorPolicy::Collect,
+            OnError::FailFast => ErrorPolicy::FailFast,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SortKey {
+    /// By file name, with numbers compared by value (file2 before file10)
+    Name,
+    /// By size, smallest first
+    Size,
+    /// By modification time, oldest first
+    Mtime,
+    /// In the order the filesystem returns entries
+    None,
+}
+
+impl From<SortKey> for SortBy {
+    fn from(key: SortKey) -> Self {
+        match key {
+            SortKey::Name => SortBy::Name,
+            SortKey::Size => SortBy::Size,
+            SortKey::Mtime => SortBy::Modified,
+            SortKey::None => SortBy::None,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum TypeArg {
+    /// Regular files
+    F,
+    /// Named pipes (FIFOs)
+    P,
+    /// Unix domain sockets
+    S,
+    /// Character devices
+    C,
+    /// Block devices
+    B,
+}
+
+impl From<TypeArg> for FindType {
+    fn from(arg: TypeArg) -> Self {
+        match arg {
+            TypeArg::F => FindType::File,
+            TypeArg::P => FindType::Special(SpecialKind::Fifo),
+            TypeArg::S => FindType::Special(SpecialKind::Socket),
+            TypeArg::C => FindType::Special(SpecialKind::CharDevice),
+            TypeArg::B => FindType::Special(SpecialKind::BlockDevice),
+        }
+    }
+}
@@ -1,69 +1,180 @@
-use crate::error::FsError;
+use crate::error::{ErrorSummary, FsError};
 use crate::filter::PathFilter;
-use crate::walk::{FsVisitor, walk_dir};
-use std::fs;
+use crate::walk::{
+    EntryMeta, FsVisitor, MetadataNeeds, ParallelFsVisitor, PartialReason, SpecialKind,
+    SymlinkInfo, WalkControl, WalkOptions, walk_dir_parallel, walk_roots_once,
+};
 use std::path::{Path, PathBuf};
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct FsStats {
     pub total_files: usize,
     pub total_dirs: usize,
     pub total_symlinks: usize,
+    /// Symlinks whose target does not exist
+    pub dangling_symlinks: usize,
+    pub total_fifos: usize,
+    pub total_sockets: usize,
+    pub total_char_devices: usize,
+    pub total_block_devices: usize,
     pub total_size: u64,
     pub largest_file: Option<(PathBuf, u64)>,
+    /// Depth of the deepest entry that was counted, measured from the root like
+    /// [`WalkOptions::max_depth`]. Entries above [`WalkOptions::min_depth`] are not
+    /// counted, so this is 0 if nothing was, and at most the max depth otherwise.
     pub max_depth: usize,
+    /// Directories on other filesystems that were not entered, see
+    /// [`WalkOptions::one_file_system`]
+    pub skipped_mount_points: Vec<PathBuf>,
 }
 
 pub struct FsStatsReport {
     pub stats: FsStats,
     pub errors: Vec<FsError>,
+    /// Set when the walk ended early because of a limit in the [`WalkOptions`], in
+    /// which case `stats` only cover part of the tree
+    pub partial: Option<PartialReason>,
 }
 
+/// Statistics over several roots, see [`collect_roots`]
+pub struct MultiRootReport {
+    /// A full report for every root, in the order the roots were given
+    pub roots: Vec<(PathBuf, FsStatsReport)>,
+    /// All roots together. Entries reached from more than one root are counted once.
+    pub total: FsStats,
+    /// Set when the walk of any root ended early, see [`FsStatsReport::partial`]
+    pub partial: Option<PartialReason>,
+}
+
+impl FsStatsReport {
+    /// The errors grouped by kind
+    pub fn error_summary(&self) -> ErrorSummary {
+        ErrorSummary::from_errors(&self.errors)
+    }
+}
+
+impl MultiRootReport {
+    /// The errors of all roots grouped by kind
+    pub fn error_summary(&self) -> ErrorSummary {
+        ErrorSummary::from_errors(self.roots.iter().flat_map(|(_, report)| &report.errors))
+    }
+}
+
+impl FsStats {
+    /// Add the statistics of `other` to these
+    pub fn merge(&mut self, other: FsStats) {
+        self.total_files += other.total_files;
+        self.total_dirs += other.total_dirs;
+        self.total_symlinks += other.total_symlinks;
+        self.dangling_symlinks += other.dangling_symlinks;
+        self.total_fifos += other.total_fifos;
+        self.total_sockets += other.total_sockets;
+        self.total_char_devices += other.total_char_devices;
+        self.total_block_devices += other.total_block_devices;
+        self.total_size += other.total_size;
+        if let Some((path, size)) = other.largest_file
+            && self
+                .largest_file
+                .as_ref()
+                .is_none_or(|(_, largest_size)| size > *largest_size)
+        {
+            self.largest_file = Some((path, size));
+        }
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.skipped_mount_points.extend(other.skipped_mount_points);
+    }
+}
+
+/// The visitor behind [`collect`], for combining it with other visitors in one walk,
+/// see [`compose`](crate::walk::compose)
 #[derive(Default)]
-struct StatsVisitor {
+pub struct StatsVisitor {
     stats: FsStats,
     errs: Vec<FsError>,
 }
 
 impl FsVisitor for StatsVisitor {
-    fn visit_file(&mut self, path: &Path, meta: &fs::Metadata, depth: usize) {
-        let size = meta.len();
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        // Fetched up front, see `metadata_needs`
+        let Ok(size) = meta.size() else {
+            return WalkControl::Continue;
+        };
         self.stats.total_files += 1;
         self.stats.total_size += size;
         if self
             .stats
             .largest_file
             .as_ref()
-            .map_or(true, |(_, largest_size)| size > *largest_size)
+            .is_none_or(|(_, largest_size)| size > *largest_size)
         {
             self.stats.largest_file = Some((path.to_path_buf(), size));
         }
         self.stats.max_depth = self.stats.max_depth.max(depth);
+        WalkControl::Continue
     }
 
-    fn enter_dir(&mut self, _path: &Path, _meta: &fs::Metadata, depth: usize) {
+    fn enter_dir(&mut self, _path: &Path, _meta: &EntryMeta, depth: usize) -> WalkControl {
         self.stats.total_dirs += 1;
         self.stats.max_depth = self.stats.max_depth.max(depth);
+        WalkControl::Continue
     }
 
-    fn exit_dir(&mut self, _path: &Path, _meta: &fs::Metadata, _depth: usize) {
-        /* We do all work when entering dir */
-    }
-
-    fn visit_symlink(&mut self, _path: &Path, _depth: usize) {
+    fn visit_symlink(&mut self, _path: &Path, link: &SymlinkInfo, _depth: usize) {
         self.stats.total_symlinks += 1;
+        if !link.resolves {
+            self.stats.dangling_symlinks += 1;
+        }
     }
 
     fn on_error(&mut self, error: FsError) {
         self.errs.push(error);
     }
+
+    fn skip_mount_point(&mut self, path: &Path, _depth: usize) {
+        self.stats.skipped_mount_points.push(path.to_path_buf());
+    }
+
+    fn visit_special(
+        &mut self,
+        _path: &Path,
+        _meta: &EntryMeta,
+        kind: SpecialKind,
+        depth: usize,
+    ) -> WalkControl {
+        match kind {
+            SpecialKind::Fifo => self.stats.total_fifos += 1,
+            SpecialKind::Socket => self.stats.total_sockets += 1,
+            SpecialKind::CharDevice => self.stats.total_char_devices += 1,
+            SpecialKind::BlockDevice => self.stats.total_block_devices += 1,
+        }
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+        WalkControl::Continue
+    }
+
+    // Only file sizes are counted, everything else goes by file type
+    fn metadata_needs(&self) -> MetadataNeeds {
+        MetadataNeeds::Size
+    }
+}
+
+impl ParallelFsVisitor for StatsVisitor {
+    fn fork(&self) -> Self {
+        Self::default()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.stats.merge(other.stats);
+        self.errs.extend(other.errs);
+    }
 }
 
 impl StatsVisitor {
-    fn into_report(self) -> FsStatsReport {
+    /// The statistics collected so far. `partial` is what the walk returned.
+    pub fn into_report(self, partial: Option<PartialReason>) -> FsStatsReport {
         FsStatsReport {
             stats: self.stats,
             errors: self.errs,
+            partial,
         }
     }
 }
@@ -73,10 +184,86 @@ pub fn collect(
     max_depth: Option<usize>,
     follow_symlinks: bool,
     filter: &dyn PathFilter,
+) -> FsStatsReport {
+    let options = WalkOptions {
+        max_depth,
+        follow_symlinks,
+        ..Default::default()
+    };
+    collect_with_options(root, filter, &options)
+}
+
+/// Like [`collect`], but with all walk options available. Walks in parallel when
+/// `options.threads` asks for more than one worker.
+pub fn collect_with_options(
+    root: &Path,
+    filter: &dyn PathFilter,
+    options: &WalkOptions,
 ) -> FsStatsReport {
     let mut visitor = StatsVisitor::default();
 
-    walk_dir(root, &mut visitor, filter, max_depth, follow_symlinks);
+    let partial = walk_dir_parallel(root, &mut visitor, filter, options);
+
+    visitor.into_report(partial)
+}
+
+/// Collect statistics for several roots, each with its own filter.
+///
+/// Every root gets a full report of its own. Roots that lie inside another root, or
+/// are given twice, are left out of the total since the other root already covers
+/// them. They are counted with the filter of the outer root there. When the walk of
+/// the outer root doesn't get to them, because its filter ignores them or they are
+/// deeper than `options.max_depth`, they are counted on their own instead.
+///
+/// The limits in `options` apply to every root on its own, except for the deadline
+/// and cancel token which are shared.
+pub fn collect_roots(roots: &[(&Path, &dyn PathFilter)], options: &WalkOptions) -> MultiRootReport {
+    // An empty `Vec` of visitors visits nothing
+    collect_roots_with(roots, options, Vec::<StatsVisitor>::new()).0
+}
+
+/// Like [`collect_roots`], but also walks `visitor` along, so that its results come
+/// out of the same pass over the tree as the statistics. `visitor` only walks the
+/// roots that are counted in the total, so it sees every entry once.
+pub fn collect_roots_with<V: ParallelFsVisitor>(
+    roots: &[(&Path, &dyn PathFilter)],
+    options: &WalkOptions,
+    visitor: V,
+) -> (MultiRootReport, V) {
+    let paths: Vec<_> = roots.iter().map(|(root, _)| *root).collect();
 
-    visitor.into_report()
+    let mut total = FsStats::default();
+    let mut reports: Vec<Option<FsStatsReport>> = roots.iter().map(|_| None).collect();
+    // Moved into the walk of each root and back out
+    let mut visitor = Some(visitor);
+    let covered = walk_roots_once(&paths, |idx, reached| {
+        let (root, filter) = roots[idx];
+        let mut all = (StatsVisitor::default(), visitor.take()?, reached);
+        let partial = walk_dir_parallel(root, &mut all, filter, options);
+        let (stats, rest, reached) = all;
+        visitor = Some(rest);
+        let root_report = stats.into_report(partial);
+        total.merge(root_report.stats.clone());
+        reports[idx] = Some(root_report);
+        Some(reached)
+    });
+    for idx in covered {
+        let (root, filter) = roots[idx];
+        reports[idx] = Some(collect_with_options(root, filter, options));
+    }
+
+    let mut report = MultiRootReport {
+        roots: Vec::new(),
+        total,
+        partial: None,
+    };
+    for ((root, _), root_report) in roots.iter().zip(reports) {
+        let root_report = root_report.expect("every root is walked");
+        report.partial = report.partial.or(root_report.partial);
+        report.roots.push((root.to_path_buf(), root_report));
+    }
+    (
+        report,
+        visitor.expect("the visitor is handed back after every walk"),
+    )
 }
@@ -1,4 +1,5 @@
 use core::fmt;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use regex;
 
@@ -6,6 +7,55 @@ use regex;
 pub enum FsError {
     Io(PathBuf, std::io::Error),
     RegexError(regex::Error),
+    /// An ignore file that exists but couldn't be read
+    IgnoreFile(PathBuf, std::io::Error),
+    /// An ignore pattern that isn't a valid glob
+    InvalidPattern(PatternError),
+}
+
+/// Where an invalid ignore pattern came from and what is wrong with it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternError {
+    /// The ignore file the pattern is in, `None` for patterns given directly
+    pub file: Option<PathBuf>,
+    /// The line of `file` the pattern is on, or its position among the patterns
+    /// given directly. Starts at 1.
+    pub line: usize,
+    /// The pattern as it was written
+    pub pattern: String,
+    pub reason: String,
+}
+
+impl FsError {
+    /// The kind of error, used to group errors in an [`ErrorSummary`]
+    pub fn kind(&self) -> FsErrorKind {
+        match self {
+            FsError::Io(_, err) => FsErrorKind::Io(err.kind()),
+            FsError::RegexError(_) => FsErrorKind::Regex,
+            FsError::IgnoreFile(_, err) => FsErrorKind::Io(err.kind()),
+            FsError::InvalidPattern(_) => FsErrorKind::InvalidPattern,
+        }
+    }
+}
+
+// `io::Error` can't be cloned, so the copy is rebuilt from its parts. Needed to hand
+// the same error to several visitors.
+impl Clone for FsError {
+    fn clone(&self) -> Self {
+        match self {
+            FsError::Io(p, err) => FsError::Io(p.clone(), clone_io_error(err)),
+            FsError::RegexError(err) => FsError::RegexError(err.clone()),
+            FsError::IgnoreFile(p, err) => FsError::IgnoreFile(p.clone(), clone_io_error(err)),
+            FsError::InvalidPattern(err) => FsError::InvalidPattern(err.clone()),
+        }
+    }
+}
+
+fn clone_io_error(err: &std::io::Error) -> std::io::Error {
+    match err.raw_os_error() {
+        Some(code) => std::io::Error::from_raw_os_error(code),
+        None => std::io::Error::new(err.kind(), err.to_string()),
+    }
 }
 
 impl fmt::Display for FsError {
@@ -17,15 +67,32 @@ impl fmt::Display for FsError {
             FsError::RegexError(err) => {
                 write!(f, "{}", err)
             }
+            FsError::IgnoreFile(p, err) => {
+                write!(f, "could not read ignore file {}: {}", p.display(), err)
+            }
+            FsError::InvalidPattern(err) => {
+                write!(f, "{}", err)
+            }
         }
     }
 }
 
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:{}: ", file.display(), self.line)?;
+        }
+        write!(f, "invalid ignore pattern '{}': {}", self.pattern, self.reason)
+    }
+}
+
 impl std::error::Error for FsError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             FsError::Io(_, err) => Some(err),
             FsError::RegexError(err) => Some(err),
+            FsError::IgnoreFile(_, err) => Some(err),
+            FsError::InvalidPattern(_) => None,
         }
     }
 }
@@ -34,4 +101,77 @@ impl From<regex::Error> for FsError {
     fn from(err: regex::Error) -> Self {
         FsError::RegexError(err)
     }
+}
+
+/// What kind of error an [`FsError`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FsErrorKind {
+    Io(std::io::ErrorKind),
+    Regex,
+    InvalidPattern,
+}
+
+impl fmt::Display for FsErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsErrorKind::Io(kind) => write!(f, "{}", kind),
+            FsErrorKind::Regex => write!(f, "invalid regex"),
+            FsErrorKind::InvalidPattern => write!(f, "invalid ignore pattern"),
+        }
+    }
+}
+
+/// The number of errors of every kind. Displays as the counts by kind, most
+/// frequent first, like `2 permission denied, 1 entity not found`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorSummary {
+    counts: BTreeMap<FsErrorKind, usize>,
+}
+
+impl ErrorSummary {
+    pub fn from_errors<'a>(errors: impl IntoIterator<Item = &'a FsError>) -> Self {
+        let mut summary = Self::default();
+        for err in errors {
+            summary.add(err.kind());
+        }
+        summary
+    }
+
+    pub fn add(&mut self, kind: FsErrorKind) {
+        *self.counts.entry(kind).or_default() += 1;
+    }
+
+    /// The number of errors of the given kind
+    pub fn count(&self, kind: FsErrorKind) -> usize {
+        self.counts.get(&kind).copied().unwrap_or(0)
+    }
+
+    /// The number of errors of all kinds together
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// The kinds that occurred with their counts, most frequent first
+    pub fn iter(&self) -> impl Iterator<Item = (FsErrorKind, usize)> + '_ {
+        let mut counts: Vec<_> = self.counts.iter().map(|(k, c)| (*k, *c)).collect();
+        // Stable sort, so kinds with the same count stay in a fixed order
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts.into_iter()
+    }
+}
+
+impl fmt::Display for ErrorSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, (kind, count)) in self.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", count, kind)?;
+        }
+        Ok(())
+    }
 }
\ No newline at end of file
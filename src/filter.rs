@@ -1,124 +1,514 @@
-use globset::{Glob, GlobMatcher};
-use std::fs::File;
+use crate::error::{FsError, PatternError};
+use git::Repo;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::{
     io::{self, BufRead},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
 };
 
-pub trait PathFilter {
+mod git;
+
+/// Decides which paths are skipped during a walk.
+///
+/// Filters are shared between the worker threads of a parallel walk and must
+/// therefore be `Sync`.
+pub trait PathFilter: Sync {
     fn is_ignored(&self, path: &Path, is_dir: bool) -> bool;
+
+    /// Called before the entries of `dir` are checked. Walks may list several
+    /// directories at once, and breadth first walks list a directory after its
+    /// parent has been exited.
+    ///
+    /// In a parallel walk this and [`exit_dir`](Self::exit_dir) are called
+    /// concurrently from several threads, and the calls for one directory are not
+    /// nested within those of its siblings or of other subtrees. State has to be
+    /// keyed by `dir` rather than kept on a stack.
+    fn enter_dir(&self, _dir: &Path) {}
+
+    /// Called once the walk is done with `dir` and everything below it, on
+    /// whichever thread finished last in a parallel walk
+    fn exit_dir(&self, _dir: &Path) {}
 }
 
 pub struct GitIgnoreFilter {
     root: PathBuf,
+    // The root without symlinks and relative components, as in the resolved paths
+    // of the entries below followed symlinks
+    canonical_root: Option<PathBuf>,
+    patterns: Vec<GitignorePattern>,
+    // `patterns[overrides..]` came from the command line and take precedence over
+    // nested ignore files, the ones before came from the ignore files of the root
+    // and the ones given explicitly
+    overrides: usize,
+    // Paths relative to the root that are left out along with everything below them
+    excluded: HashSet<PathBuf>,
+    // The names of the ignore files looked for in every directory, lowest
+    // precedence first
+    file_names: Vec<&'static str>,
+    // Ignore files below the root, when they are looked for
+    nested: Option<NestedIgnores>,
+    // Ignore files that apply to the whole repository, from the highest precedence
+    // to the lowest, checked after all other ignore files
+    excludes: Vec<RepoIgnores>,
+    // Invalid patterns and unreadable ignore files that were left out
+    errors: Mutex<Vec<FsError>>,
+}
+
+/// Which ignore files a [`GitIgnoreFilter`] applies, see
+/// [`GitIgnoreFilter::with_options`]
+#[derive(Clone, Debug)]
+pub struct IgnoreOptions {
+    /// Apply the ignore files that are found without being asked for: `.fsxignore`,
+    /// `.ignore` and `.gitignore` files, and git's excludes files. When off, only
+    /// explicitly given patterns and files apply. On by default.
+    pub automatic: bool,
+    /// Apply git's ignore files, `.gitignore` files and the excludes files. On by
+    /// default.
+    pub vcs_ignore: bool,
+    /// Apply the user's global excludes file, `core.excludesFile` in the git config
+    /// or `$XDG_CONFIG_HOME/git/ignore` when that isn't set, when the root is in a
+    /// git repository. On by default.
+    pub global_ignore: bool,
+    /// More files of gitignore patterns. Relative file names are relative to the
+    /// root of the walk, and so are the patterns.
+    pub ignore_files: Vec<PathBuf>,
+    /// Files listing paths to leave out, one per line. Relative file names and
+    /// relative paths in the files are relative to the root of the walk, and no
+    /// pattern syntax is recognised.
+    pub exclude_from: Vec<PathBuf>,
+}
+
+impl Default for IgnoreOptions {
+    fn default() -> Self {
+        Self {
+            automatic: true,
+            vcs_ignore: true,
+            global_ignore: true,
+            ignore_files: Vec::new(),
+            exclude_from: Vec::new(),
+        }
+    }
+}
+
+impl IgnoreOptions {
+    // The names of the ignore files to look for in every directory, lowest
+    // precedence first
+    fn file_names(&self) -> Vec<&'static str> {
+        match (self.automatic, self.vcs_ignore) {
+            (false, _) => Vec::new(),
+            (true, false) => vec![".ignore", ".fsxignore"],
+            (true, true) => vec![".gitignore", ".ignore", ".fsxignore"],
+        }
+    }
+}
+
+// Patterns relative to the top of the repository rather than to the root of the
+// walk, like the ones in `.git/info/exclude`
+struct RepoIgnores {
+    // The root of the walk relative to the top of the repository
+    root_in_repo: PathBuf,
     patterns: Vec<GitignorePattern>,
 }
 
-// TODO: Return Result<Self> from new()
+// The patterns of the ignore files in the directories the walk is in and the ones
+// above them, by directory. Every entered directory holds itself and the
+// directories between it and the root, and a directory is dropped once the last of
+// them was exited. So only the directories that walks are in stay loaded, whatever
+// order they are entered and exited in. Anything else asked about is loaded on the
+// spot and not kept.
+#[derive(Default)]
+struct NestedIgnores {
+    dirs: RwLock<HashMap<PathBuf, HeldIgnores>>,
+}
+
+struct HeldIgnores {
+    // `None` for directories without any ignore files
+    patterns: Option<Arc<Vec<GitignorePattern>>>,
+    // The entered directories holding it
+    holders: usize,
+}
+
 // gitignore semantics:
 // - patterns are evaluated in order
 // - last match wins
+// - a slash at the start or in the middle anchors a pattern to its directory
 // - directories match both dir and children via extra matcher
 impl GitIgnoreFilter {
+    /// A filter applying just `patterns`. Invalid patterns are left out, see
+    /// [`errors`](Self::errors).
     pub fn new(root: &Path, patterns: &[String]) -> Self {
+        let mut errors = Vec::new();
+        let patterns = Self::compile(None, &Self::numbered(patterns), &mut errors);
+        Self {
+            root: root.to_path_buf(),
+            canonical_root: fs::canonicalize(root).ok(),
+            overrides: patterns.len(),
+            patterns,
+            excluded: HashSet::new(),
+            file_names: Vec::new(),
+            nested: None,
+            excludes: Vec::new(),
+            errors: Mutex::new(errors),
+        }
+    }
+
+    /// Like [`new`](Self::new), failing on the first invalid pattern
+    pub fn try_new(root: &Path, patterns: &[String]) -> Result<Self, FsError> {
+        Self::new(root, patterns).strict()
+    }
+
+    // Patterns given directly, numbered by their position
+    fn numbered(patterns: &[String]) -> Vec<(usize, String)> {
+        patterns
+            .iter()
+            .enumerate()
+            .map(|(idx, pattern)| (idx + 1, pattern.clone()))
+            .collect()
+    }
+
+    // The filter, or the first problem found while building it
+    fn strict(self) -> Result<Self, FsError> {
+        let first = self.errors.lock().unwrap().drain(..).next();
+        match first {
+            Some(err) => Err(err),
+            None => Ok(self),
+        }
+    }
+
+    // Compiles the patterns `file` has on the given lines, or the ones given
+    // directly when it is `None`. Invalid patterns are added to `errors`.
+    fn compile(
+        file: Option<&Path>,
+        patterns: &[(usize, String)],
+        errors: &mut Vec<FsError>,
+    ) -> Vec<GitignorePattern> {
         let mut compiled_patterns = Vec::new();
-        for pattern in patterns {
-            let mut cleaned = pattern.as_str();
+        for (line, pattern) in patterns {
+            let mut cleaned = trim_trailing_spaces(pattern);
 
-            // Negation
+            // Negation, `\!` is a literal `!`
             let negated = cleaned.starts_with("!");
             if negated {
                 cleaned = &cleaned[1..];
             };
 
-            // Anchored at root
-            let anchored = cleaned.starts_with("/");
-            if anchored {
-                cleaned = &cleaned[1..];
-            }
-
             // Directory only
             let dir = cleaned.ends_with("/");
             if dir {
                 cleaned = &cleaned[..cleaned.len() - 1];
             }
 
+            // Anchored at root by a slash anywhere but at the end
+            let anchored = cleaned.contains('/');
+            if let Some(rest) = cleaned.strip_prefix('/') {
+                cleaned = rest;
+            }
+
+            // Blank, or nothing but slashes and `!`
+            if cleaned.is_empty() {
+                continue;
+            }
+
             let effective_pattern = if !anchored {
-                format!("**/{}", cleaned)
+                format!("**/{}", glob_syntax(cleaned))
             } else {
-                cleaned.to_string()
+                glob_syntax(cleaned)
+            };
+
+            let mut matcher = |glob: &str| match GlobBuilder::new(glob)
+                .literal_separator(true)
+                .backslash_escape(true)
+                .build()
+            {
+                Ok(glob) => Some(glob.compile_matcher()),
+                Err(e) => {
+                    errors.push(FsError::InvalidPattern(PatternError {
+                        file: file.map(Path::to_path_buf),
+                        line: *line,
+                        pattern: pattern.clone(),
+                        reason: e.kind().to_string(),
+                    }));
+                    None
+                }
             };
 
             // normal matcher
+            let Some(normal) = matcher(&effective_pattern) else {
+                continue;
+            };
             compiled_patterns.push(GitignorePattern {
-                matcher: match Glob::new(&effective_pattern) {
-                    Ok(glob) => glob.compile_matcher(),
-                    Err(e) => {
-                        eprintln!("Warning: Invalid ignore pattern '{}': {}", pattern, e);
-                        continue;
-                    }
-                },
-                dir: dir,
-                negated: negated,
+                matcher: normal,
+                dir,
+                negated,
             });
 
             // extra matcher for directories to include children
-            if dir {
+            if dir && let Some(children) = matcher(&format!("{}/**", effective_pattern)) {
                 compiled_patterns.push(GitignorePattern {
-                    matcher: match Glob::new(&format!("{}/**", effective_pattern)) {
-                        Ok(glob) => glob.compile_matcher(),
-                        Err(e) => {
-                            eprintln!("Warning: Invalid ignore pattern '{}': {}", pattern, e);
-                            continue;
-                        }
-                    },
+                    matcher: children,
                     dir: false, // children can be files
-                    negated: negated,
+                    negated,
                 });
             }
         }
 
-        Self {
-            root: root.to_path_buf(),
-            patterns: compiled_patterns,
-        }
+        compiled_patterns
     }
 
-    fn parse_gitignore(gitignore_path: &Path) -> io::Result<Vec<String>> {
+    // The patterns in a gitignore file with their line numbers. Whitespace is kept,
+    // except for the trailing spaces `compile` drops.
+    fn parse_gitignore(gitignore_path: &Path) -> io::Result<Vec<(usize, String)>> {
         let mut patterns = Vec::new();
-        let file = File::open(&gitignore_path)?;
-        for line in io::BufReader::new(file).lines() {
-            let line = line?.trim().to_string();
-            if line.is_empty() || line.starts_with('#') {
+        let file = File::open(gitignore_path)?;
+        for (idx, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            // `\#` is a literal `#`
+            if line.starts_with('#') || trim_trailing_spaces(&line).is_empty() {
                 continue;
             }
-            patterns.push(line);
+            patterns.push((idx + 1, line));
         }
         Ok(patterns)
     }
 
+    /// Apply `root/.gitignore`, the `.gitignore` files of the directories below the
+    /// root as the walk enters them, and then `cli_patterns`. As in git, patterns in
+    /// a `.gitignore` file are relative to its directory, files deeper down take
+    /// precedence over the ones above them, and within a file the last matching
+    /// pattern wins.
+    ///
+    /// `.ignore` and `.fsxignore` files are applied the same way, taking precedence
+    /// over the `.gitignore` file in the same directory, and `.fsxignore` over
+    /// `.ignore`.
+    ///
+    /// Like git, this also applies `.git/info/exclude` of the repository the root is
+    /// in, and the user's global excludes file, both with a lower precedence than any
+    /// `.gitignore` file. Neither applies when the root isn't in a repository.
+    ///
+    /// Invalid patterns and unreadable ignore files are left out, see
+    /// [`errors`](Self::errors).
     pub fn from_gitignore(root: &Path, cli_patterns: &[String]) -> Self {
-        let mut patterns = Vec::new();
+        Self::with_options(root, cli_patterns, &IgnoreOptions::default())
+    }
 
-        // Load gitignore if it exists
-        let gitignore_path = root.join(".gitignore");
-        if gitignore_path.exists() {
-            match Self::parse_gitignore(&gitignore_path) {
-                Ok(v) => patterns.extend(v),
-                Err(e) => {
-                    eprintln!(
-                        "Warning: Could not read .gitignore file. Will continue with provided cli patterns: {}",
-                        e
-                    );
-                }
+    /// Like [`from_gitignore`](Self::from_gitignore), failing on the first invalid
+    /// pattern or unreadable ignore file
+    pub fn try_from_gitignore(root: &Path, cli_patterns: &[String]) -> Result<Self, FsError> {
+        Self::from_gitignore(root, cli_patterns).strict()
+    }
+
+    /// Like [`from_gitignore`](Self::from_gitignore), with `options` deciding which
+    /// ignore files are applied. The files in [`IgnoreOptions::ignore_files`] take
+    /// precedence over git's excludes files but not over any ignore file in the
+    /// tree. The paths in [`IgnoreOptions::exclude_from`] are always left out.
+    pub fn with_options(root: &Path, cli_patterns: &[String], options: &IgnoreOptions) -> Self {
+        let mut errors = Vec::new();
+        let mut patterns = Vec::new();
+        for path in &options.ignore_files {
+            // Unlike the files looked for on their own, these have to exist
+            let path = root.join(path);
+            match Self::parse_gitignore(&path) {
+                Ok(v) => patterns.extend(Self::compile(Some(&path), &v, &mut errors)),
+                Err(e) => errors.push(FsError::IgnoreFile(path, e)),
             }
         }
 
+        let file_names = options.file_names();
+        if let Some(v) = Self::load_dir_ignores(root, &file_names, &mut errors) {
+            patterns.extend(v);
+        }
+
         // Append cli patterns
-        patterns.extend_from_slice(cli_patterns);
+        let overrides = patterns.len();
+        patterns.extend(Self::compile(
+            None,
+            &Self::numbered(cli_patterns),
+            &mut errors,
+        ));
+
+        let canonical_root = fs::canonicalize(root).ok();
+        let mut excluded = HashSet::new();
+        for path in &options.exclude_from {
+            let path = root.join(path);
+            match fs::read_to_string(&path) {
+                Ok(list) => excluded.extend(Self::parse_path_list(
+                    root,
+                    canonical_root.as_deref(),
+                    &list,
+                )),
+                Err(e) => errors.push(FsError::IgnoreFile(path, e)),
+            }
+        }
 
-        // Construct the filter using constructor
-        GitIgnoreFilter::new(root, &patterns)
+        let excludes = if options.automatic && options.vcs_ignore {
+            Self::repo_ignores(root, options, &mut errors)
+        } else {
+            Vec::new()
+        };
+        Self {
+            root: root.to_path_buf(),
+            canonical_root,
+            patterns,
+            overrides,
+            excluded,
+            nested: (!file_names.is_empty()).then(NestedIgnores::default),
+            file_names,
+            excludes,
+            errors: Mutex::new(errors),
+        }
+    }
+
+    /// Like [`with_options`](Self::with_options), failing on the first invalid
+    /// pattern or unreadable ignore file.
+    ///
+    /// Ignore files below the root are only read once the walk gets to them, so
+    /// problems with those can't fail this. They are left out and recorded as
+    /// usual, see [`errors`](Self::errors).
+    pub fn try_with_options(
+        root: &Path,
+        cli_patterns: &[String],
+        options: &IgnoreOptions,
+    ) -> Result<Self, FsError> {
+        Self::with_options(root, cli_patterns, options).strict()
+    }
+
+    /// The invalid patterns and unreadable ignore files that were left out so far,
+    /// [`FsError::InvalidPattern`] and [`FsError::IgnoreFile`] errors. Ignore files
+    /// below the root are read during the walk, so this is only complete after it.
+    pub fn errors(&self) -> Vec<FsError> {
+        self.errors.lock().unwrap().clone()
+    }
+
+    // The paths relative to `root` in a list of paths, one per line. Absolute paths
+    // may start with the root as given or with its canonical form. Ones outside of
+    // the root can't match anything and are dropped.
+    fn parse_path_list(root: &Path, canonical_root: Option<&Path>, list: &str) -> Vec<PathBuf> {
+        list.lines()
+            .filter_map(|line| {
+                let path = Path::new(line);
+                let path = if path.is_absolute() {
+                    path.strip_prefix(root)
+                        .ok()
+                        .or_else(|| path.strip_prefix(canonical_root?).ok())?
+                } else {
+                    path
+                };
+                // Leave out `.` components, so that `./a/b` is `a/b`
+                let path: PathBuf = path
+                    .components()
+                    .filter(|c| *c != Component::CurDir)
+                    .collect();
+                (!path.as_os_str().is_empty()).then_some(path)
+            })
+            .collect()
+    }
+
+    // `info/exclude` of the repository `root` is in and the global excludes file.
+    // Like git, neither applies outside of a repository.
+    fn repo_ignores(
+        root: &Path,
+        options: &IgnoreOptions,
+        errors: &mut Vec<FsError>,
+    ) -> Vec<RepoIgnores> {
+        let repo = Repo::find(root);
+        let root_in_repo = match &repo {
+            Some(repo) => fs::canonicalize(root)
+                .ok()
+                .and_then(|root| Some(root.strip_prefix(&repo.toplevel).ok()?.to_path_buf()))
+                .unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+
+        let mut files = Vec::new();
+        if let Some(repo) = &repo {
+            files.push(repo.info_exclude());
+            if options.global_ignore {
+                files.extend(git::global_excludes_file(repo));
+            }
+        }
+        files
+            .iter()
+            .filter_map(|path| Self::load_ignore_file(path, errors))
+            .map(|patterns| RepoIgnores {
+                root_in_repo: root_in_repo.clone(),
+                patterns,
+            })
+            .collect()
+    }
+
+    // The compiled patterns of the ignore file at `path`, `None` if there is none.
+    // Problems with the file are added to `errors`.
+    fn load_ignore_file(path: &Path, errors: &mut Vec<FsError>) -> Option<Vec<GitignorePattern>> {
+        match Self::parse_gitignore(path) {
+            Ok(patterns) => Some(Self::compile(Some(path), &patterns, errors)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                errors.push(FsError::IgnoreFile(path.to_path_buf(), e));
+                None
+            }
+        }
+    }
+
+    // The patterns of the ignore files named `file_names` in `dir`, one file after
+    // the other. `None` if there are none of them.
+    fn load_dir_ignores(
+        dir: &Path,
+        file_names: &[&str],
+        errors: &mut Vec<FsError>,
+    ) -> Option<Vec<GitignorePattern>> {
+        file_names
+            .iter()
+            .filter_map(|name| Self::load_ignore_file(&dir.join(name), errors))
+            .reduce(|mut patterns, more| {
+                patterns.extend(more);
+                patterns
+            })
+    }
+
+    // The patterns of the ignore files in `dir`, loading them if needed
+    fn nested_patterns(
+        &self,
+        nested: &NestedIgnores,
+        dir: &Path,
+    ) -> Option<Arc<Vec<GitignorePattern>>> {
+        if let Some(held) = nested.dirs.read().unwrap().get(dir) {
+            return held.patterns.clone();
+        }
+        self.load_nested(dir)
+    }
+
+    // Load the ignore files of `dir` and record their problems, unless the same
+    // problems were recorded already
+    fn load_nested(&self, dir: &Path) -> Option<Arc<Vec<GitignorePattern>>> {
+        let mut errors = Vec::new();
+        let patterns = Self::load_dir_ignores(dir, &self.file_names, &mut errors).map(Arc::new);
+        if !errors.is_empty() {
+            let mut recorded = self.errors.lock().unwrap();
+            for err in errors {
+                let message = err.to_string();
+                if !recorded.iter().any(|known| known.to_string() == message) {
+                    recorded.push(err);
+                }
+            }
+        }
+        patterns
+    }
+
+    // The directories from `dir` up to the root, without the root itself. Empty for
+    // the root and for directories outside of it.
+    fn dirs_below_root<'p>(&self, dir: &'p Path) -> impl Iterator<Item = &'p Path> {
+        let root = if dir.starts_with(&self.root) {
+            Some(self.root.as_path())
+        } else {
+            self.canonical_root
+                .as_deref()
+                .filter(|root| dir.starts_with(root))
+        };
+        dir.ancestors()
+            .take_while(move |ancestor| root.is_some_and(|root| *ancestor != root))
     }
 
     pub fn patterns(&self) -> &[GitignorePattern] {
@@ -140,20 +530,154 @@ impl GitignorePattern {
 
 impl PathFilter for GitIgnoreFilter {
     fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // Entries below followed symlinks come with their resolved path
         let rel_path = match path.strip_prefix(&self.root) {
             Ok(p) => p,
-            Err(_e) => return false,
+            Err(_e) => match self
+                .canonical_root
+                .as_ref()
+                .and_then(|root| path.strip_prefix(root).ok())
+            {
+                Some(p) => p,
+                None => return false,
+            },
+        };
+        if !self.excluded.is_empty() && rel_path.ancestors().any(|p| self.excluded.contains(p)) {
+            return true;
+        }
+        let (root_patterns, cli_patterns) = self.patterns.split_at(self.overrides);
+        if let Some(ignored) = last_match(cli_patterns, rel_path, is_dir) {
+            return ignored;
+        }
+
+        // The directories between the entry and the root, deepest first
+        if let Some(nested) = &self.nested {
+            let between = rel_path.components().count().saturating_sub(1);
+            for dir in path.ancestors().skip(1).take(between) {
+                let Some(patterns) = self.nested_patterns(nested, dir) else {
+                    continue;
+                };
+                let rel_path = path.strip_prefix(dir).expect("dir is an ancestor");
+                if let Some(ignored) = last_match(&patterns, rel_path, is_dir) {
+                    return ignored;
+                }
+            }
+        }
+
+        if let Some(ignored) = last_match(root_patterns, rel_path, is_dir) {
+            return ignored;
+        }
+
+        self.excludes
+            .iter()
+            .find_map(|excludes| {
+                let path = excludes.root_in_repo.join(rel_path);
+                last_match(&excludes.patterns, &path, is_dir)
+            })
+            .unwrap_or(false)
+    }
+
+    fn enter_dir(&self, dir: &Path) {
+        let Some(nested) = &self.nested else {
+            return;
         };
-        let mut ignored = false;
-        for pat in &self.patterns {
-            if pat.dir && !is_dir {
+        for ancestor in self.dirs_below_root(dir) {
+            if let Some(held) = nested.dirs.write().unwrap().get_mut(ancestor) {
+                held.holders += 1;
                 continue;
             }
+            // Loaded without the lock, another thread may have loaded it meanwhile
+            let patterns = self.load_nested(ancestor);
+            nested
+                .dirs
+                .write()
+                .unwrap()
+                .entry(ancestor.to_path_buf())
+                .or_insert(HeldIgnores {
+                    patterns,
+                    holders: 0,
+                })
+                .holders += 1;
+        }
+    }
+
+    fn exit_dir(&self, dir: &Path) {
+        let Some(nested) = &self.nested else {
+            return;
+        };
+        let mut dirs = nested.dirs.write().unwrap();
+        for ancestor in self.dirs_below_root(dir) {
+            if let Some(entry) = dirs.get_mut(ancestor) {
+                entry.holders -= 1;
+                if entry.holders == 0 {
+                    dirs.remove(ancestor);
+                }
+            }
+        }
+    }
+}
+
+// Whether the last of `patterns` matching `rel_path` ignores it, `None` if none match
+fn last_match(patterns: &[GitignorePattern], rel_path: &Path, is_dir: bool) -> Option<bool> {
+    patterns
+        .iter()
+        .rev()
+        .find(|pat| (is_dir || !pat.dir) && pat.matcher.is_match(rel_path))
+        .map(|pat| !pat.negated)
+}
+
+// A pattern without its trailing spaces, unless they are escaped with a backslash
+fn trim_trailing_spaces(pattern: &str) -> &str {
+    let mut end = 0;
+    let mut chars = pattern.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            ' ' => {}
+            // The escaped character is kept, whatever it is
+            '\\' => {
+                end = match chars.next() {
+                    Some((idx, c)) => idx + c.len_utf8(),
+                    None => idx + 1,
+                }
+            }
+            c => end = idx + c.len_utf8(),
+        }
+    }
+    &pattern[..end]
+}
 
-            if pat.matcher.is_match(rel_path) {
-                ignored = !pat.negated;
+// A gitignore pattern in the syntax of `globset`. Braces have no special meaning in
+// git, and `**` is only special as a whole path component. Elsewhere it is a plain
+// `*`, which `globset` would reject.
+fn glob_syntax(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut glob = String::with_capacity(pattern.len());
+    let mut idx = 0;
+    while idx < chars.len() {
+        match chars[idx] {
+            '\\' => {
+                glob.push('\\');
+                if let Some(&c) = chars.get(idx + 1) {
+                    glob.push(c);
+                    idx += 1;
+                }
+            }
+            '{' | '}' => {
+                glob.push('\\');
+                glob.push(chars[idx]);
+            }
+            '*' => {
+                let start = idx;
+                while chars.get(idx + 1) == Some(&'*') {
+                    idx += 1;
+                }
+                let component = (start == 0 || chars[start - 1] == '/')
+                    && chars.get(idx + 1).is_none_or(|&c| c == '/');
+                glob.push_str(if idx > start && component { "**" } else { "*" });
             }
+            c => glob.push(c),
         }
-        ignored
+        idx += 1;
     }
+    glob
 }
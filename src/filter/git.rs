@@ -0,0 +1,152 @@
+// Finding the git repository around a walk and the ignore files git would apply to
+// it, without running git. Only the parts of the config format that are needed to
+// read `core.excludesFile` are understood.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+// The repository a directory belongs to
+pub(super) struct Repo {
+    // The directory containing `.git`
+    pub(super) toplevel: PathBuf,
+    // Where the config and `info/exclude` live. For worktrees this is the git
+    // directory of the main worktree.
+    pub(super) git_dir: PathBuf,
+}
+
+impl Repo {
+    // The repository `dir` is in, found by looking for `.git` in it and its parents
+    pub(super) fn find(dir: &Path) -> Option<Self> {
+        let dir = fs::canonicalize(dir).ok()?;
+        dir.ancestors().find_map(|toplevel| {
+            let dot_git = toplevel.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else {
+                // Worktrees and submodules have a `.git` file pointing to the git
+                // directory instead
+                let contents = fs::read_to_string(&dot_git).ok()?;
+                let linked = toplevel.join(contents.strip_prefix("gitdir:")?.trim());
+                match fs::read_to_string(linked.join("commondir")) {
+                    Ok(common) => linked.join(common.trim()),
+                    Err(_) => linked,
+                }
+            };
+            Some(Self {
+                toplevel: toplevel.to_path_buf(),
+                git_dir,
+            })
+        })
+    }
+
+    pub(super) fn info_exclude(&self) -> PathBuf {
+        self.git_dir.join("info").join("exclude")
+    }
+}
+
+// The user's global excludes file: `core.excludesFile` from the git config, or
+// `$XDG_CONFIG_HOME/git/ignore` when that isn't set. Config files are read in the
+// order git reads them, so the repository's own config has the last word.
+pub(super) fn global_excludes_file(repo: &Repo) -> Option<PathBuf> {
+    let home = env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from);
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(home.as_ref()?.join(".config")));
+
+    let configs = [
+        config_home
+            .as_ref()
+            .map(|dir| dir.join("git").join("config")),
+        home.as_ref().map(|home| home.join(".gitconfig")),
+        Some(repo.git_dir.join("config")),
+    ];
+    // The last value set wins
+    let configured = configs
+        .into_iter()
+        .rev()
+        .flatten()
+        .filter_map(|config| fs::read_to_string(config).ok())
+        .find_map(|contents| config_value(&contents, "core", "excludesfile"));
+
+    match configured {
+        Some(path) => Some(expand_home(&path, home.as_deref())),
+        None => Some(config_home?.join("git").join("ignore")),
+    }
+}
+
+// `~` on its own or followed by `/` stands for the home directory. Other users'
+// home directories, `~user/`, aren't looked up.
+fn expand_home(path: &str, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix('~'), home) {
+        (Some(""), Some(home)) => home.to_path_buf(),
+        (Some(rest), Some(home)) if rest.starts_with('/') => home.join(&rest[1..]),
+        _ => PathBuf::from(path),
+    }
+}
+
+// The last value of `section.key` in the contents of a config file. Section and key
+// names are case insensitive, sections with a subsection don't count.
+fn config_value(contents: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    let mut value = None;
+    for line in contents.lines() {
+        let mut line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            let Some((name, rest)) = header.split_once(']') else {
+                continue;
+            };
+            in_section = name.trim().eq_ignore_ascii_case(section);
+            // A variable may follow the header on the same line
+            line = rest.trim();
+        }
+        if !in_section || line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+
+        let (name, raw) = match line.split_once('=') {
+            Some((name, raw)) => (name.trim(), raw),
+            None => (line, ""),
+        };
+        if name.eq_ignore_ascii_case(key) {
+            value = Some(parse_value(raw));
+        }
+    }
+    value
+}
+
+// A config value without its quotes, escapes and trailing comment
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    // Whitespace at the end is dropped unless it is quoted or escaped
+    let mut end = 0;
+    let mut quoted = false;
+    let mut chars = raw.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => {
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => value.push(c),
+                    None => {}
+                }
+                end = value.len();
+            }
+            '#' | ';' if !quoted => break,
+            c => {
+                value.push(c);
+                if quoted || !c.is_whitespace() {
+                    end = value.len();
+                }
+            }
+        }
+    }
+    value.truncate(end);
+    value
+}
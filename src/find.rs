@@ -1,70 +1,167 @@
-use crate::error::FsError;
+use crate::error::{ErrorSummary, FsError};
 use crate::filter::PathFilter;
-use crate::walk::{FsVisitor, walk_dir};
+use crate::walk::{
+    EntryMeta, FsVisitor, MetadataNeeds, ParallelFsVisitor, PartialReason, SpecialKind,
+    SymlinkInfo, WalkControl, WalkOptions, walk_dir_parallel, walk_roots_once,
+};
 use regex::Regex;
-use std::fs;
 use std::path::{Path, PathBuf};
 
 #[derive(Default, Debug)]
 pub struct FindEntry {
     pub name: PathBuf,
     pub size: u64,
+    /// Depth of the entry, measured from the root like [`WalkOptions::max_depth`].
+    /// Always within the min and max depth of the walk.
     pub depth: usize,
 }
 
+/// Kind of entries to look for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FindType {
+    /// Regular files
+    #[default]
+    File,
+    /// Special files of the given kind
+    Special(SpecialKind),
+    /// Symlinks whose target does not exist
+    BrokenSymlink,
+}
+
 #[derive(Debug)]
 pub struct FindReport {
     pub entries: Vec<FindEntry>,
     pub errors: Vec<FsError>,
+    /// Directories on other filesystems that were not entered, see
+    /// [`WalkOptions::one_file_system`]
+    pub skipped_mount_points: Vec<PathBuf>,
+    /// Set when the walk ended early because of a limit in the [`WalkOptions`], in
+    /// which case `entries` only cover part of the tree
+    pub partial: Option<PartialReason>,
+}
+
+impl FindReport {
+    /// The errors grouped by kind
+    pub fn error_summary(&self) -> ErrorSummary {
+        ErrorSummary::from_errors(&self.errors)
+    }
 }
 
-struct FindVisitor {
+/// The visitor behind [`find`], for combining it with other visitors in one walk, see
+/// [`compose`](crate::walk::compose)
+pub struct FindVisitor {
     pattern: Regex,
+    find_type: FindType,
     entries: Vec<FindEntry>,
     errors: Vec<FsError>,
+    skipped_mount_points: Vec<PathBuf>,
 }
 
 impl FindVisitor {
-    pub fn new(regex: &str) -> Result<Self, FsError> {
+    /// Looks for entries of `find_type` whose name matches `regex`. Fails if `regex`
+    /// is invalid.
+    pub fn new(regex: &str, find_type: FindType) -> Result<Self, FsError> {
         let re = Regex::new(regex)?;
         Ok(Self {
             pattern: re,
+            find_type,
             entries: Vec::new(),
             errors: Vec::new(),
+            skipped_mount_points: Vec::new(),
         })
     }
 }
 
 impl FsVisitor for FindVisitor {
-    fn visit_file(&mut self, path: &Path, meta: &fs::Metadata, depth: usize) {
-        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
-            return;
-        };
-        if self.pattern.is_match(file_name) {
-            self.entries.push(
-                FindEntry { name: path.to_path_buf(), size: meta.len(), depth: depth }
-            );
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        if self.find_type == FindType::File {
+            self.check(path, depth, || meta.size());
         }
+        WalkControl::Continue
     }
 
-    fn enter_dir(&mut self, _path: &Path, _meta: &fs::Metadata, _depth: usize) {
+    fn enter_dir(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
         /* TODO: Add support for searching for directories */
+        WalkControl::Continue
     }
 
-    fn exit_dir(&mut self, _path: &Path, _meta: &fs::Metadata, _depth: usize) {}
-
-    fn visit_symlink(&mut self, _path: &Path, _depth: usize) {}
+    fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+        if self.find_type == FindType::BrokenSymlink && !link.resolves {
+            // The size of a symlink is the length of its target
+            self.check(path, depth, || Ok(link.target.as_os_str().len() as u64));
+        }
+    }
 
     fn on_error(&mut self, error: FsError) {
         self.errors.push(error);
     }
+
+    fn skip_mount_point(&mut self, path: &Path, _depth: usize) {
+        self.skipped_mount_points.push(path.to_path_buf());
+    }
+
+    fn visit_special(
+        &mut self,
+        path: &Path,
+        meta: &EntryMeta,
+        kind: SpecialKind,
+        depth: usize,
+    ) -> WalkControl {
+        if self.find_type == FindType::Special(kind) {
+            self.check(path, depth, || meta.size());
+        }
+        WalkControl::Continue
+    }
+
+    // Names come from the listing, so only matching entries need their metadata
+    fn metadata_needs(&self) -> MetadataNeeds {
+        MetadataNeeds::FileType
+    }
+}
+
+impl ParallelFsVisitor for FindVisitor {
+    fn fork(&self) -> Self {
+        Self {
+            pattern: self.pattern.clone(),
+            find_type: self.find_type,
+            entries: Vec::new(),
+            errors: Vec::new(),
+            skipped_mount_points: Vec::new(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.entries.extend(other.entries);
+        self.errors.extend(other.errors);
+        self.skipped_mount_points.extend(other.skipped_mount_points);
+    }
 }
 
 impl FindVisitor {
-    fn into_report(self) -> FindReport {
+    // Record the entry if its name matches the pattern. The size is only looked up
+    // for matches.
+    fn check(&mut self, path: &Path, depth: usize, size: impl FnOnce() -> Result<u64, FsError>) {
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            return;
+        };
+        if !self.pattern.is_match(file_name) {
+            return;
+        }
+        match size() {
+            Ok(size) => self.entries.push(
+                FindEntry { name: path.to_path_buf(), size, depth }
+            ),
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    /// The entries found so far. `partial` is what the walk returned.
+    pub fn into_report(self, partial: Option<PartialReason>) -> FindReport {
         FindReport {
             entries: self.entries,
             errors: self.errors,
+            skipped_mount_points: self.skipped_mount_points,
+            partial,
         }
     }
 }
@@ -76,23 +173,89 @@ pub fn find(
     ignore_filter: &dyn PathFilter,
     pattern: &str,
 ) -> FindReport {
-    let mut visitor = match FindVisitor::new(pattern) {
+    let options = WalkOptions {
+        max_depth,
+        follow_symlinks,
+        ..Default::default()
+    };
+    find_with_options(root, ignore_filter, pattern, &options)
+}
+
+/// Like [`find`], but with all walk options available. Walks in parallel when
+/// `options.threads` asks for more than one worker, in which case the order of
+/// the returned entries is not deterministic.
+pub fn find_with_options(
+    root: &Path,
+    ignore_filter: &dyn PathFilter,
+    pattern: &str,
+    options: &WalkOptions,
+) -> FindReport {
+    find_by_type(root, ignore_filter, pattern, FindType::File, options)
+}
+
+/// Like [`find_with_options`], but looks for entries of `find_type` instead of
+/// regular files
+pub fn find_by_type(
+    root: &Path,
+    ignore_filter: &dyn PathFilter,
+    pattern: &str,
+    find_type: FindType,
+    options: &WalkOptions,
+) -> FindReport {
+    let mut visitor = match FindVisitor::new(pattern, find_type) {
         Ok(vis) => vis,
         Err(err) => {
             return FindReport {
                 entries: Vec::new(),
                 errors: vec![err],
+                skipped_mount_points: Vec::new(),
+                partial: None,
             };
         }
     };
 
-    walk_dir(
-        root,
-        &mut visitor,
-        ignore_filter,
-        max_depth,
-        follow_symlinks,
-    );
+    let partial = walk_dir_parallel(root, &mut visitor, ignore_filter, options);
+
+    visitor.into_report(partial)
+}
+
+/// Like [`find_by_type`], but searches several roots, each with its own filter.
+/// Roots that lie inside another root, or are given twice, are not searched again
+/// when the other root's search got to them, so every entry is listed once. The
+/// limits in `options` apply to every root on its own, except for the deadline and
+/// cancel token which are shared. Once a limit is hit, the remaining roots are not
+/// searched.
+pub fn find_roots(
+    roots: &[(&Path, &dyn PathFilter)],
+    pattern: &str,
+    find_type: FindType,
+    options: &WalkOptions,
+) -> FindReport {
+    let visitor = match FindVisitor::new(pattern, find_type) {
+        Ok(vis) => vis,
+        Err(err) => {
+            return FindReport {
+                entries: Vec::new(),
+                errors: vec![err],
+                skipped_mount_points: Vec::new(),
+                partial: None,
+            };
+        }
+    };
+
+    let paths: Vec<_> = roots.iter().map(|(root, _)| *root).collect();
+    let mut partial = None;
+    let mut visitor = Some(visitor);
+    walk_roots_once(&paths, |idx, reached| {
+        let (root, filter) = roots[idx];
+        let mut both = (visitor.take()?, reached);
+        partial = walk_dir_parallel(root, &mut both, filter, options);
+        let (rest, reached) = both;
+        visitor = Some(rest);
+        partial.is_none().then_some(reached)
+    });
 
-    visitor.into_report()
+    visitor
+        .expect("the visitor is handed back after every walk")
+        .into_report(partial)
 }
@@ -7,8 +7,23 @@ pub mod walk; // filesystem walking
 pub mod collect_stats;
 pub mod find;
 
+// Async walks, only with the `async` feature
+#[cfg(feature = "async")]
+pub mod async_walk;
+
 // Re-export the main API at the crate root
-pub use collect_stats::collect;
+pub use collect_stats::{
+    MultiRootReport, StatsVisitor, collect, collect_roots, collect_roots_with,
+    collect_with_options,
+};
+pub use error::{ErrorSummary, FsError, FsErrorKind, PatternError};
 pub use filter::{PathFilter, GitIgnoreFilter};
-pub use find::{FindReport, find};
-pub use walk::{FsVisitor, walk_dir};
+pub use find::{
+    FindReport, FindType, FindVisitor, find, find_by_type, find_roots, find_with_options,
+};
+pub use walk::{
+    CancelToken, EntryMeta, EntryType, ErrorPolicy, FsVisitor, MetadataNeeds, ParallelFsVisitor,
+    PartialReason, Progress, ProgressReporter, SortBy, SpecialKind, SymlinkInfo, WalkControl,
+    WalkEntry, WalkEvent, WalkOptions, WalkStrategy, Walker, walk_dir, walk_dir_parallel,
+    walk_dir_with_options,
+};
@@ -2,50 +2,158 @@ mod cli;
 mod output;
 
 use clap::Parser;
-use fsx::{collect, find};
-use fsx::filter::GitIgnoreFilter;
+use fsx::{FindType, FindVisitor, FsError, PathFilter, collect_roots_with, find_roots};
+use fsx::filter::{GitIgnoreFilter, IgnoreOptions};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let cli = cli::Cli::parse();
 
     match cli.command {
         cli::Commands::Stats {
-            path,
-            max_depth,
+            paths,
+            walk,
+            errors,
             format,
-            follow_symlinks,
             ignore,
+            ignore_sources,
+            list_matches,
         } => {
-            let ignore_filter =
-                GitIgnoreFilter::from_gitignore(&path, &ignore.unwrap_or(Vec::new()));
+            let filters = root_filters(
+                &paths,
+                &ignore.unwrap_or(Vec::new()),
+                &ignore_sources.to_options(),
+            );
+            let options = walk.to_options();
 
-            let report = collect(&path, max_depth, follow_symlinks, &ignore_filter);
+            // At most one finder, walked along with the statistics
+            let finders: Vec<FindVisitor> = match list_matches {
+                Some(pattern) => match FindVisitor::new(&pattern, FindType::File) {
+                    Ok(finder) => vec![finder],
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                },
+                None => Vec::new(),
+            };
+            let (report, finders) =
+                collect_roots_with(&roots(&paths, &filters), &options, finders);
 
-            output::print_stats(&report.stats, format);
-            for err in report.errors {
-                eprintln!("{}", err);
+            output::print_root_stats(&report, format.clone());
+            for finder in finders {
+                // Errors were reported to the statistics as well
+                let matches = finder.into_report(report.partial);
+                println!();
+                println!("Matches:");
+                output::print_find_entries(&matches.entries, format.clone());
+            }
+            if let Some(reason) = report.partial {
+                eprintln!("Results are incomplete: {}", reason);
+            }
+            print_filter_warnings(&filters);
+            output::print_errors(
+                report.roots.iter().flat_map(|(_, root_report)| &root_report.errors),
+                &errors,
+            );
+            if !report.error_summary().is_empty() {
+                std::process::exit(1);
             }
         }
 
         cli::Commands::Find {
-            path,
+            paths,
             regex,
-            max_depth,
+            entry_type,
+            broken_symlinks,
+            walk,
+            errors,
             format,
-            follow_symlinks,
             ignore,
+            ignore_sources,
         } => {
-            let ignore_filter =
-                GitIgnoreFilter::from_gitignore(&path, &ignore.unwrap_or(Vec::new()));
+            let filters = root_filters(
+                &paths,
+                &ignore.unwrap_or(Vec::new()),
+                &ignore_sources.to_options(),
+            );
+            let options = walk.to_options();
 
             let pattern = regex.unwrap_or(".*".to_string());
+            let find_type = if broken_symlinks {
+                FindType::BrokenSymlink
+            } else {
+                entry_type.into()
+            };
 
-            let report = find(&path, max_depth, follow_symlinks, &ignore_filter, &pattern);
+            let report = find_roots(&roots(&paths, &filters), &pattern, find_type, &options);
             
             output::print_find_entries(&report.entries, format);
-            for err in report.errors {
-                eprintln!("{}", err);
+            if let Some(reason) = report.partial {
+                eprintln!("Results are incomplete: {}", reason);
+            }
+            for path in report.skipped_mount_points {
+                eprintln!("Skipped mount point: {}", path.display());
+            }
+            print_filter_warnings(&filters);
+            output::print_errors(&report.errors, &errors);
+            if !report.errors.is_empty() {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Every root gets its own ignore files, combined with the patterns from the CLI.
+// Files given with --ignore-file and --exclude-from that can't be read are fatal.
+fn root_filters(
+    paths: &[PathBuf],
+    cli_patterns: &[String],
+    options: &IgnoreOptions,
+) -> Vec<GitIgnoreFilter> {
+    let filters: Vec<GitIgnoreFilter> = paths
+        .iter()
+        .map(|path| GitIgnoreFilter::with_options(path, cli_patterns, options))
+        .collect();
+
+    let explicit: Vec<&PathBuf> = options
+        .ignore_files
+        .iter()
+        .chain(&options.exclude_from)
+        .collect();
+    for (root, filter) in paths.iter().zip(&filters) {
+        for err in filter.errors() {
+            if let FsError::IgnoreFile(path, _) = &err
+                && explicit.iter().any(|file| root.join(file) == *path)
+            {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
             }
         }
     }
+    filters
+}
+
+// Invalid patterns and unreadable ignore files are left out without failing the
+// command. Patterns from the CLI are shared by all roots and reported once.
+fn print_filter_warnings(filters: &[GitIgnoreFilter]) {
+    let mut seen = HashSet::new();
+    for err in filters.iter().flat_map(|filter| filter.errors()) {
+        let message = err.to_string();
+        if seen.insert(message.clone()) {
+            eprintln!("Warning: {}", message);
+        }
+    }
+}
+
+fn roots<'a>(
+    paths: &'a [PathBuf],
+    filters: &'a [GitIgnoreFilter],
+) -> Vec<(&'a Path, &'a dyn PathFilter)> {
+    paths
+        .iter()
+        .zip(filters)
+        .map(|(path, filter)| (path.as_path(), filter as &dyn PathFilter))
+        .collect()
 }
@@ -1,5 +1,9 @@
-use fsx::collect_stats::FsStats;
+use crate::cli::ErrorArgs;
+use fsx::collect_stats::{FsStats, MultiRootReport};
+use fsx::error::{ErrorSummary, FsError};
 use fsx::find::FindEntry;
+use fsx::{Progress, ProgressReporter};
+use std::time::Duration;
 
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum OutputFormat {
@@ -25,11 +29,14 @@ pub fn print_stats(stats: &FsStats, format: OutputFormat) {
             println!("Files: {}", stats.total_files);
             println!("Dirs: {}", stats.total_dirs);
             println!("Symlinks: {}", stats.total_symlinks);
+            println!("Dangling symlinks: {}", stats.dangling_symlinks);
+            print_special_files(stats);
             println!("Size: {} bytes", stats.total_size);
             if let Some((path, size)) = &stats.largest_file {
                 println!("Largest file: {} ({} bytes)", path.display(), size);
             }
             println!("Max depth: {}", stats.max_depth);
+            print_skipped_mount_points(stats);
         }
         OutputFormat::Debug => {
             println!("{:?}", stats);
@@ -38,6 +45,8 @@ pub fn print_stats(stats: &FsStats, format: OutputFormat) {
             println!("Files: {}", stats.total_files);
             println!("Dirs: {}", stats.total_dirs);
             println!("Symlinks: {}", stats.total_symlinks);
+            println!("Dangling symlinks: {}", stats.dangling_symlinks);
+            print_special_files(stats);
             println!("Size: {}", convert_to_human_readable(stats.total_size));
             if let Some((path, size)) = &stats.largest_file {
                 println!(
@@ -47,10 +56,39 @@ pub fn print_stats(stats: &FsStats, format: OutputFormat) {
                 );
             }
             println!("Max depth: {}", stats.max_depth);
+            print_skipped_mount_points(stats);
         }
     }
 }
 
+// Print the statistics of a single root as is, and those of several roots one
+// after the other followed by their total
+pub fn print_root_stats(report: &MultiRootReport, format: OutputFormat) {
+    if let [(_, root_report)] = report.roots.as_slice() {
+        return print_stats(&root_report.stats, format);
+    }
+    for (path, root_report) in &report.roots {
+        println!("{}:", path.display());
+        print_stats(&root_report.stats, format.clone());
+        println!();
+    }
+    println!("Total:");
+    print_stats(&report.total, format);
+}
+
+fn print_special_files(stats: &FsStats) {
+    println!("FIFOs: {}", stats.total_fifos);
+    println!("Sockets: {}", stats.total_sockets);
+    println!("Char devices: {}", stats.total_char_devices);
+    println!("Block devices: {}", stats.total_block_devices);
+}
+
+fn print_skipped_mount_points(stats: &FsStats) {
+    for path in &stats.skipped_mount_points {
+        println!("Skipped mount point: {}", path.display());
+    }
+}
+
 pub fn print_find_entries(entries: &[FindEntry], format: OutputFormat) {
     match format {
         OutputFormat::Raw => {
@@ -68,3 +106,61 @@ pub fn print_find_entries(entries: &[FindEntry], format: OutputFormat) {
         }
     }
 }
+
+// Print errors to stderr, at most `--max-errors` of them and none with
+// `--quiet-errors`, followed by their number by kind
+pub fn print_errors<'a>(errors: impl IntoIterator<Item = &'a FsError>, args: &ErrorArgs) {
+    let errors: Vec<_> = errors.into_iter().collect();
+    if errors.is_empty() {
+        return;
+    }
+    if !args.quiet_errors {
+        let shown = args.max_errors.unwrap_or(errors.len()).min(errors.len());
+        for err in &errors[..shown] {
+            eprintln!("{}", err);
+        }
+        if shown < errors.len() {
+            eprintln!("... and {} more", errors.len() - shown);
+        }
+    }
+    let summary = ErrorSummary::from_errors(errors.iter().copied());
+    eprintln!("Errors: {} ({})", summary.total(), summary);
+}
+
+// On a terminal the progress is a single line that is redrawn and cleared at the
+// end. Elsewhere a line is written every few seconds.
+pub fn progress_reporter(tty: bool) -> ProgressReporter {
+    if tty {
+        ProgressReporter::new(Duration::from_millis(100), |progress| {
+            if progress.done {
+                eprint!("\r\x1b[2K");
+            } else {
+                eprint!("\r\x1b[2K{}", progress_line(progress));
+            }
+        })
+    } else {
+        ProgressReporter::new(Duration::from_secs(5), |progress| {
+            if !progress.done {
+                eprintln!("{}", progress_line(progress));
+            }
+        })
+    }
+}
+
+fn progress_line(progress: &Progress) -> String {
+    // Keep the line short enough not to wrap, so it can be redrawn
+    let dir = progress.current_dir.display().to_string();
+    let dir = match dir.char_indices().rev().nth(49) {
+        Some((idx, _)) => format!("...{}", &dir[idx..]),
+        None => dir,
+    };
+    // Sizes are only known when the command looks them up anyway
+    let bytes = match progress.bytes {
+        Some(bytes) => format!(", {}", convert_to_human_readable(bytes)),
+        None => String::new(),
+    };
+    format!(
+        "{} entries{}, {} errors, in {}",
+        progress.entries, bytes, progress.errors, dir
+    )
+}
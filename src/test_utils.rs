@@ -4,10 +4,14 @@ use crate::filter::GitIgnoreFilter;
 pub enum FsNode<'a> {
     File(&'a str, &'a str),
     Dir(&'a str, Vec<FsNode<'a>>),
-    #[cfg(target_os = "windows")]
+    #[cfg(any(target_os = "windows", unix))]
     SymlinkFile(&'a str, &'a str), // (link name, target relative path)
-    #[cfg(target_os = "windows")]
+    #[cfg(any(target_os = "windows", unix))]
     SymlinkDir(&'a str, &'a str),  // (link name, target relative path)
+    #[cfg(unix)]
+    Fifo(&'a str),
+    #[cfg(unix)]
+    Socket(&'a str),
 }
 
 pub fn create_fs_tree(root: &Path, node: &FsNode) -> std::io::Result<()> {
@@ -22,17 +26,37 @@ pub fn create_fs_tree(root: &Path, node: &FsNode) -> std::io::Result<()> {
                 create_fs_tree(&dir, child)?;
             }
         }
-        #[cfg(target_os = "windows")]
+        #[cfg(any(target_os = "windows", unix))]
         FsNode::SymlinkFile(name, target) => {
             let link_path = root.join(name);
             let target_path = root.join(target);
+            #[cfg(target_os = "windows")]
             std::os::windows::fs::symlink_file(target_path, link_path)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(target_path, link_path)?;
         }
-        #[cfg(target_os = "windows")]
+        #[cfg(any(target_os = "windows", unix))]
         FsNode::SymlinkDir(name, target) => {
             let link_path = root.join(name);
             let target_path = root.join(target);
+            #[cfg(target_os = "windows")]
             std::os::windows::fs::symlink_dir(target_path, link_path)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(target_path, link_path)?;
+        }
+        #[cfg(unix)]
+        FsNode::Fifo(name) => {
+            use std::os::unix::ffi::OsStrExt;
+            let path = std::ffi::CString::new(root.join(name).as_os_str().as_bytes())?;
+            // SAFETY: `path` is a valid NUL terminated string
+            if unsafe { libc::mkfifo(path.as_ptr(), 0o644) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        #[cfg(unix)]
+        FsNode::Socket(name) => {
+            // The socket file stays behind when the listener is dropped
+            std::os::unix::net::UnixListener::bind(root.join(name))?;
         }
     }
     Ok(())
@@ -41,4 +65,21 @@ pub fn create_fs_tree(root: &Path, node: &FsNode) -> std::io::Result<()> {
 pub fn gitignore_filter(root: &Path, patterns: &[&str]) -> GitIgnoreFilter {
     let patterns: Vec<String> = patterns.iter().map(|s| s.to_string()).collect();
     GitIgnoreFilter::new(root, &patterns)
+}
+
+/// Number of metadata lookups (`stat` calls) walks have made on the current thread
+/// so far. Only walks on a single thread are counted completely.
+#[cfg(feature = "test-hooks")]
+pub fn stat_calls() -> usize {
+    crate::walk::stat_calls()
+}
+
+/// Run `f` with walks on the current thread looking up metadata the way they do
+/// when `/proc` isn't mounted, for [`WalkOptions::dir_fds`](crate::WalkOptions::dir_fds)
+#[cfg(all(feature = "test-hooks", target_os = "linux"))]
+pub fn without_proc_fd<R>(f: impl FnOnce() -> R) -> R {
+    crate::walk::disable_proc_fd(true);
+    let result = f();
+    crate::walk::disable_proc_fd(false);
+    result
 }
\ No newline at end of file
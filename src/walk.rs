@@ -1,25 +1,206 @@
 use crate::error::FsError;
 use crate::filter::PathFilter;
-use std::{
-    collections::HashSet,
-    fs::{self, Metadata},
-    path::{Path, PathBuf},
-};
+use progress::ProgressTracker;
+use std::{path::Path, thread, time::Instant};
 
+pub mod adapters;
+pub mod compose;
+#[cfg(target_os = "linux")]
+mod dirfd;
+mod limits;
+mod parallel;
+mod progress;
+mod roots;
+mod sort;
+mod walker;
+
+pub use limits::{CancelToken, ErrorPolicy, PartialReason};
+pub use parallel::{ParallelFsVisitor, walk_dir_parallel};
+pub use progress::{Progress, ProgressReporter};
+pub(crate) use roots::walk_roots_once;
+pub use sort::{EntryComparator, SortBy, natural_cmp};
+#[cfg(all(feature = "test-hooks", target_os = "linux"))]
+pub(crate) use dirfd::disable_proc_fd;
+#[cfg(feature = "test-hooks")]
+pub(crate) use walker::stat_calls;
+pub use walker::{EntryMeta, EntryType, SpecialKind, SymlinkInfo, WalkEntry, WalkEvent, Walker};
+
+/// Returned by [`FsVisitor::visit_file`] and [`FsVisitor::enter_dir`] to steer the walk.
+/// Ordered from least to most strict.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WalkControl {
+    /// Keep walking as usual
+    #[default]
+    Continue,
+    /// From `enter_dir`: don't descend into the directory, `exit_dir` is still called.
+    /// From `visit_file`: skip the remaining entries of the directory containing the file.
+    SkipSubtree,
+    /// Stop the walk. No further callbacks are made, not even `exit_dir` for the
+    /// directories that are currently entered.
+    Stop,
+}
+
+/// Lets closures that never steer the walk return nothing, see
+/// [`adapters::FnVisitor`]
+impl From<()> for WalkControl {
+    fn from(_: ()) -> Self {
+        WalkControl::Continue
+    }
+}
+
+/// What metadata a visitor needs, see [`FsVisitor::metadata_needs`]. Ordered from
+/// least to most.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MetadataNeeds {
+    /// Only the file type, which comes with the directory listing on most platforms
+    FileType,
+    /// The metadata of regular files, for their size. Other entries only need their
+    /// file type.
+    Size,
+    /// The metadata of every entry
+    #[default]
+    Full,
+}
+
+/// Callbacks made while walking a tree. They all do nothing and keep walking unless
+/// implemented, see [`adapters`] for building visitors out of closures.
 pub trait FsVisitor {
-    fn visit_file(&mut self, path: &Path, meta: &Metadata, depth: usize);
-    fn enter_dir(&mut self, path: &Path, meta: &Metadata, depth: usize);
-    fn exit_dir(&mut self, path: &Path, meta: &Metadata, depth: usize);
-    fn visit_symlink(&mut self, path: &Path, depth: usize);
-    fn on_error(&mut self, error: FsError);
+    fn visit_file(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn enter_dir(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn exit_dir(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) {}
+    fn visit_symlink(&mut self, _path: &Path, _link: &SymlinkInfo, _depth: usize) {}
+    /// Errors are dropped unless this is implemented
+    fn on_error(&mut self, _error: FsError) {}
+    /// Called for directories on another filesystem that are not entered because
+    /// [`WalkOptions::one_file_system`] is set
+    fn skip_mount_point(&mut self, _path: &Path, _depth: usize) {}
+    /// Called instead of visiting a directory or symlink target a second time when
+    /// following symlinks, see [`WalkEvent::Cycle`]
+    fn visit_cycle(&mut self, _path: &Path, _depth: usize) {}
+    /// Called for FIFOs, sockets and device nodes. Returns a [`WalkControl`] like
+    /// [`visit_file`](FsVisitor::visit_file).
+    fn visit_special(
+        &mut self,
+        _path: &Path,
+        _meta: &EntryMeta,
+        _kind: SpecialKind,
+        _depth: usize,
+    ) -> WalkControl {
+        WalkControl::Continue
+    }
+    /// Which metadata the walk fetches before making a callback. An entry whose
+    /// metadata can't be fetched is reported to `on_error` instead of its callback.
+    /// Anything else can still be fetched through the [`EntryMeta`] passed to the
+    /// callbacks, so needing less only saves a `stat` call per entry for the entries
+    /// that don't end up needing it.
+    fn metadata_needs(&self) -> MetadataNeeds {
+        MetadataNeeds::Full
+    }
 }
 
-struct WalkContext<'a, V: FsVisitor> {
-    max_depth: usize,
-    follow_symlinks: bool,
-    visited: HashSet<PathBuf>,
-    filter: &'a dyn PathFilter,
-    visitor: &'a mut V,
+/// Options controlling how a directory tree is walked
+#[derive(Clone, Debug)]
+pub struct WalkOptions {
+    /// Maximum depth to recurse to. Depth starts at 1 for entries directly under the root.
+    pub max_depth: Option<usize>,
+    /// Minimum depth to report entries at. Shallower directories are still walked,
+    /// but their entries are not reported to visitors or yielded by a [`Walker`].
+    pub min_depth: Option<usize>,
+    /// Recurse into symbolic links
+    pub follow_symlinks: bool,
+    /// Report and filter entries below followed symlinks by their path through the
+    /// link, like `find -L`, instead of by the resolved path of the link's target.
+    /// [`WalkEntry::physical_path`] or [`std::fs::canonicalize`]
+    /// give the resolved path.
+    pub logical_paths: bool,
+    /// Number of worker threads. 1 walks on the calling thread, 0 uses all available cores.
+    pub threads: usize,
+    /// Order in which the entries of each directory are visited
+    pub sort: SortBy,
+    /// Reverse the order given by `sort`
+    pub sort_reverse: bool,
+    /// Order in which directories are descended into
+    pub strategy: WalkStrategy,
+    /// Don't descend into directories on another filesystem than the root. Only
+    /// supported on Unix, elsewhere this has no effect.
+    pub one_file_system: bool,
+    /// Stops the walk once cancelled
+    pub cancel: Option<CancelToken>,
+    /// Stop the walk after reporting this many entries. `exit_dir` calls don't count.
+    pub max_entries: Option<usize>,
+    /// Stop the walk once this point in time has passed
+    pub deadline: Option<Instant>,
+    /// Receives progress reports while walking
+    pub progress: Option<ProgressReporter>,
+    /// Whether errors are reported to the visitor, and whether they stop the walk
+    pub error_policy: ErrorPolicy,
+    /// Open directories relative to their parent's file descriptor and look up
+    /// metadata relative to it, instead of resolving full paths from the root every
+    /// time. Visitors still get the full paths, and symlinks are still resolved by
+    /// path. Only supported on Linux, elsewhere this has no effect.
+    ///
+    /// Metadata other than the file type is looked up through the directory's entry
+    /// in `/proc/self/fd`, as std can't build a [`Metadata`](std::fs::Metadata) from
+    /// `fstatat`. Without `/proc` mounted, each lookup opens the entry with `O_PATH`
+    /// instead, which takes more system calls.
+    ///
+    /// A directory is only kept open while the walk is in it. Depth first walks
+    /// open every directory relative to its parent, so they handle paths longer
+    /// than `PATH_MAX` and keep walking the same directory if it is renamed midway.
+    /// Breadth first and parallel walks get to most directories after their parent
+    /// was closed. Those are opened by path, and the walk reports an error instead
+    /// of entering them if they were replaced since they were listed.
+    pub dir_fds: bool,
+}
+
+/// Order in which a walk descends into directories
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalkStrategy {
+    /// Walk the whole subtree of a directory before moving on to its next sibling.
+    /// `exit_dir` is called once the whole subtree has been visited.
+    #[default]
+    DepthFirst,
+    /// Visit everything at one depth before going deeper, so shallow entries come
+    /// first. Directories are queued when found and entered in turn: `enter_dir`,
+    /// then the direct entries of the directory, then `exit_dir`. Subdirectories are
+    /// queued rather than descended into, so they are entered after `exit_dir`.
+    BreadthFirst,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            min_depth: None,
+            follow_symlinks: false,
+            logical_paths: false,
+            threads: 1,
+            sort: SortBy::None,
+            sort_reverse: false,
+            strategy: WalkStrategy::DepthFirst,
+            one_file_system: false,
+            cancel: None,
+            max_entries: None,
+            deadline: None,
+            progress: None,
+            error_policy: ErrorPolicy::Collect,
+            dir_fds: false,
+        }
+    }
+}
+
+impl WalkOptions {
+    /// The number of worker threads to actually start
+    pub fn worker_count(&self) -> usize {
+        match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+    }
 }
 
 pub fn walk_dir<V: FsVisitor>(
@@ -29,112 +210,107 @@ pub fn walk_dir<V: FsVisitor>(
     max_depth: Option<usize>,
     follow_symlinks: bool,
 ) {
-    let mut context = WalkContext {
-        max_depth: max_depth.unwrap_or(usize::MAX),
-        follow_symlinks: follow_symlinks,
-        visited: HashSet::new(),
-        filter: filter,
-        visitor: visitor,
+    let options = WalkOptions {
+        max_depth,
+        follow_symlinks,
+        ..Default::default()
     };
-    walk_dir_internal(root, &mut context, 1);
+    walk_dir_with_options(root, visitor, filter, &options);
 }
 
-// Walk `root` and call `f` for every entry.
-fn walk_dir_internal<V: FsVisitor>(root: &Path, ctx: &mut WalkContext<V>, depth: usize) {
-    let entries = match fs::read_dir(root) {
-        Ok(rd) => rd,
-        Err(e) => {
-            ctx.visitor.on_error(FsError::Io(root.to_path_buf(), e));
-            return;
-        }
-    };
-
-    for entry_res in entries {
-        let entry = match entry_res {
-            Ok(e) => e,
-            Err(e) => {
-                ctx.visitor.on_error(FsError::Io(root.to_path_buf(), e));
-                continue;
-            }
-        };
-        let path = entry.path();
-
-        let is_dir = match entry.file_type() {
-            Ok(ft) => ft.is_dir(),
+/// Like [`walk_dir`], but with all walk options available. Always walks on the
+/// calling thread, see [`walk_dir_parallel`] for using several threads.
+///
+/// Returns why the walk ended early if one of the limits in `options` was hit. Like
+/// [`WalkControl::Stop`], that ends the walk without calling `exit_dir` for the
+/// directories that are currently entered.
+pub fn walk_dir_with_options<V: FsVisitor>(
+    root: &Path,
+    visitor: &mut V,
+    filter: &dyn PathFilter,
+    options: &WalkOptions,
+) -> Option<PartialReason> {
+    let progress = ProgressTracker::new(options.progress.clone(), visitor.metadata_needs());
+    let mut walker = Walker::new(root, filter, options);
+    if visitor.metadata_needs() == MetadataNeeds::Full {
+        walker.stat_dirs();
+    }
+    while let Some(entry_res) = walker.next() {
+        let control = match entry_res {
+            Ok(entry) => match dispatch(visitor, &entry) {
+                Ok(control) => {
+                    progress.entry(&entry);
+                    control
+                }
+                Err(e) => {
+                    if walker.allow_error() {
+                        progress.error();
+                        visitor.on_error(e);
+                    }
+                    WalkControl::Continue
+                }
+            },
+            // Errors from the walker itself have already passed the error policy
             Err(e) => {
-                ctx.visitor.on_error(FsError::Io(path, e));
-                continue;
+                progress.error();
+                visitor.on_error(e);
+                WalkControl::Continue
             }
         };
-
-        if ctx.filter.is_ignored(&path, is_dir) {
-            continue;
+        match control {
+            WalkControl::Continue => {}
+            WalkControl::SkipSubtree => walker.skip_current_dir(),
+            WalkControl::Stop => break,
         }
+    }
+    progress.finish();
+    walker.partial()
+}
 
-        let meta = match entry.metadata() {
-            Ok(m) => m,
-            Err(e) => {
-                ctx.visitor.on_error(FsError::Io(path.to_path_buf(), e));
-                continue;
-            }
-        };
-
-        if meta.is_symlink() {
-            ctx.visitor.visit_symlink(&path, depth);
-            if ctx.follow_symlinks {
-                let target = match fs::canonicalize(&path) {
-                    Ok(tg) => tg,
-                    Err(e) => {
-                        ctx.visitor.on_error(FsError::Io(path.to_path_buf(), e));
-                        continue;
-                    }
-                };
-
-                if ctx.visited.insert(target.clone()) {
-                    let target_meta = match fs::metadata(&target) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            ctx.visitor.on_error(FsError::Io(target.to_path_buf(), e));
-                            continue;
-                        }
-                    };
-
-                    // Make sure that we don't visit ignored symlink targets
-                    if ctx.filter.is_ignored(&target, target_meta.is_dir()) {
-                        continue;
-                    }
-
-                    if target_meta.is_dir() {
-                        ctx.visitor.enter_dir(&target, &target_meta, depth);
-                        if depth < ctx.max_depth {
-                            walk_dir_internal(&target, ctx, depth + 1);
-                        }
-                        ctx.visitor.exit_dir(&target, &target_meta, depth);
-                    } else if target_meta.is_file() {
-                        ctx.visitor.visit_file(&target, &target_meta, depth);
-                    }
-                }
-            }
-        } else if meta.is_dir() {
-            if ctx.follow_symlinks {
-                let target = match fs::canonicalize(&path) {
-                    Ok(tg) => tg,
-                    Err(e) => {
-                        ctx.visitor.on_error(FsError::Io(path.to_path_buf(), e));
-                        continue;
-                    }
-                };
-                if !ctx.visited.insert(target) {
-                    continue;
-                }
-            }
-            ctx.visitor.enter_dir(&path, &meta, depth);
-            if depth < ctx.max_depth {
-                walk_dir_internal(&path, ctx, depth + 1);
-            }
-            ctx.visitor.exit_dir(&path, &meta, depth);
-        } else if meta.is_file() {
-            ctx.visitor.visit_file(&path, &meta, depth);
+// Report a single entry to the matching visitor callback. Fails if the metadata
+// the visitor needs can't be fetched.
+fn dispatch<V: FsVisitor>(visitor: &mut V, entry: &WalkEntry) -> Result<WalkControl, FsError> {
+    let path = entry.path();
+    let depth = entry.depth();
+    match entry.event() {
+        WalkEvent::Symlink => {
+            let link = entry
+                .symlink()
+                .expect("symlinks are resolved before they are reported");
+            visitor.visit_symlink(path, link, depth);
+            return Ok(WalkControl::Continue);
         }
+        WalkEvent::MountPoint => {
+            visitor.skip_mount_point(path, depth);
+            return Ok(WalkControl::Continue);
+        }
+        WalkEvent::Cycle => {
+            visitor.visit_cycle(path, depth);
+            return Ok(WalkControl::Continue);
+        }
+        _ => {}
+    }
+
+    let prefetch = match visitor.metadata_needs() {
+        MetadataNeeds::FileType => false,
+        MetadataNeeds::Size => entry.event() == WalkEvent::File,
+        MetadataNeeds::Full => true,
+    };
+    if prefetch {
+        entry.metadata()?;
     }
+    let meta = &EntryMeta::new(entry);
+    let control = match entry.event() {
+        WalkEvent::File => visitor.visit_file(path, meta, depth),
+        WalkEvent::Special(kind) => visitor.visit_special(path, meta, kind, depth),
+        WalkEvent::EnterDir => visitor.enter_dir(path, meta, depth),
+        WalkEvent::ExitDir => {
+            visitor.exit_dir(path, meta, depth);
+            WalkControl::Continue
+        }
+        WalkEvent::Symlink | WalkEvent::MountPoint | WalkEvent::Cycle => {
+            unreachable!("handled above")
+        }
+    };
+    Ok(control)
 }
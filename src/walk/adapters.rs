@@ -0,0 +1,503 @@
+//! Visitors built out of closures, and adapters for existing visitors.
+//!
+//! Every callback of a walk can be seen as a [`Visit`]. [`FnVisitor`] hands them all
+//! to one closure, [`Counter`] counts and [`Collector`] collects the ones a closure
+//! picks, and [`FsVisitorExt`] filters them or rewrites their paths before they reach
+//! another visitor:
+//!
+//! ```no_run
+//! # use fsx::walk::adapters::{Collector, Counter, FnVisitor, FsVisitorExt, Visit};
+//! # use fsx::{StatsVisitor, WalkOptions, walk_dir_with_options};
+//! # use fsx::filter::GitIgnoreFilter;
+//! # use std::path::Path;
+//! let root = Path::new(".");
+//! let filter = GitIgnoreFilter::new(root, &[]);
+//! let options = WalkOptions::default();
+//!
+//! let mut dirs = FnVisitor::new(|visit| {
+//!     if let Visit::EnterDir { path, .. } = visit {
+//!         println!("{}", path.display());
+//!     }
+//! });
+//! walk_dir_with_options(root, &mut dirs, &filter, &options);
+//!
+//! let mut rust_files = Counter::new(|visit| {
+//!     matches!(visit, Visit::File { path, .. } if path.extension() == Some("rs".as_ref()))
+//! });
+//! walk_dir_with_options(root, &mut rust_files, &filter, &options);
+//!
+//! let mut large = Collector::new(|visit| match visit {
+//!     Visit::File { path, meta, .. } if meta.size().ok()? > 1 << 20 => Some(path.to_path_buf()),
+//!     _ => None,
+//! });
+//! walk_dir_with_options(root, &mut large, &filter, &options);
+//!
+//! // Statistics over everything but the tests
+//! let mut stats = StatsVisitor::default()
+//!     .filter(|visit| !visit.path().is_some_and(|path| path.starts_with("./tests")));
+//! walk_dir_with_options(root, &mut stats, &filter, &options);
+//! ```
+//!
+//! The closures of [`FnVisitor`], [`Counter`] and [`Collector`] get the metadata
+//! through [`EntryMeta`], which only looks it up when it is used, see
+//! [`MetadataNeeds::FileType`].
+
+use crate::error::FsError;
+use crate::walk::{
+    EntryMeta, FsVisitor, MetadataNeeds, ParallelFsVisitor, SpecialKind, SymlinkInfo, WalkControl,
+};
+use std::path::{Path, PathBuf};
+
+/// One callback of a walk, see [`FsVisitor`] for what each of them means
+#[derive(Clone, Copy, Debug)]
+pub enum Visit<'a> {
+    File {
+        path: &'a Path,
+        meta: EntryMeta<'a>,
+        depth: usize,
+    },
+    EnterDir {
+        path: &'a Path,
+        meta: EntryMeta<'a>,
+        depth: usize,
+    },
+    ExitDir {
+        path: &'a Path,
+        meta: EntryMeta<'a>,
+        depth: usize,
+    },
+    Symlink {
+        path: &'a Path,
+        link: &'a SymlinkInfo,
+        depth: usize,
+    },
+    Special {
+        path: &'a Path,
+        meta: EntryMeta<'a>,
+        kind: SpecialKind,
+        depth: usize,
+    },
+    MountPoint {
+        path: &'a Path,
+        depth: usize,
+    },
+    Cycle {
+        path: &'a Path,
+        depth: usize,
+    },
+    Error(&'a FsError),
+}
+
+impl<'a> Visit<'a> {
+    /// The path of the entry, `None` for errors
+    pub fn path(&self) -> Option<&'a Path> {
+        match *self {
+            Visit::File { path, .. }
+            | Visit::EnterDir { path, .. }
+            | Visit::ExitDir { path, .. }
+            | Visit::Symlink { path, .. }
+            | Visit::Special { path, .. }
+            | Visit::MountPoint { path, .. }
+            | Visit::Cycle { path, .. } => Some(path),
+            Visit::Error(_) => None,
+        }
+    }
+
+    /// The depth of the entry, `None` for errors
+    pub fn depth(&self) -> Option<usize> {
+        match *self {
+            Visit::File { depth, .. }
+            | Visit::EnterDir { depth, .. }
+            | Visit::ExitDir { depth, .. }
+            | Visit::Symlink { depth, .. }
+            | Visit::Special { depth, .. }
+            | Visit::MountPoint { depth, .. }
+            | Visit::Cycle { depth, .. } => Some(depth),
+            Visit::Error(_) => None,
+        }
+    }
+}
+
+// Implements `FsVisitor` for an adapter by turning every callback into a `Visit` for
+// its `on_visit` method
+macro_rules! visit_through_on_visit {
+    (impl<$($param:ident),*> for $ty:ty where $($bound:tt)*) => {
+        impl<$($param),*> FsVisitor for $ty where $($bound)* {
+            fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+                self.on_visit(Visit::File { path, meta: *meta, depth })
+            }
+
+            fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+                self.on_visit(Visit::EnterDir { path, meta: *meta, depth })
+            }
+
+            fn exit_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) {
+                self.on_visit(Visit::ExitDir { path, meta: *meta, depth });
+            }
+
+            fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+                self.on_visit(Visit::Symlink { path, link, depth });
+            }
+
+            fn on_error(&mut self, error: FsError) {
+                self.on_visit(Visit::Error(&error));
+            }
+
+            fn skip_mount_point(&mut self, path: &Path, depth: usize) {
+                self.on_visit(Visit::MountPoint { path, depth });
+            }
+
+            fn visit_cycle(&mut self, path: &Path, depth: usize) {
+                self.on_visit(Visit::Cycle { path, depth });
+            }
+
+            fn visit_special(
+                &mut self,
+                path: &Path,
+                meta: &EntryMeta,
+                kind: SpecialKind,
+                depth: usize,
+            ) -> WalkControl {
+                self.on_visit(Visit::Special { path, meta: *meta, kind, depth })
+            }
+
+            fn metadata_needs(&self) -> MetadataNeeds {
+                MetadataNeeds::FileType
+            }
+        }
+    };
+}
+
+/// A visitor that passes every [`Visit`] to a closure. The closure returns a
+/// [`WalkControl`], or `()` to keep walking.
+pub struct FnVisitor<F> {
+    f: F,
+}
+
+impl<F> FnVisitor<F> {
+    pub fn new<R>(f: F) -> Self
+    where
+        F: FnMut(Visit<'_>) -> R,
+        R: Into<WalkControl>,
+    {
+        Self { f }
+    }
+
+    fn on_visit<R>(&mut self, visit: Visit) -> WalkControl
+    where
+        F: FnMut(Visit<'_>) -> R,
+        R: Into<WalkControl>,
+    {
+        (self.f)(visit).into()
+    }
+}
+
+visit_through_on_visit!(
+    impl<F, R> for FnVisitor<F> where F: FnMut(Visit<'_>) -> R, R: Into<WalkControl>
+);
+
+/// A visitor that counts the [`Visit`]s a closure returns `true` for
+#[derive(Clone)]
+pub struct Counter<F> {
+    pred: F,
+    count: usize,
+}
+
+impl<F: FnMut(&Visit) -> bool> Counter<F> {
+    pub fn new(pred: F) -> Self {
+        Self { pred, count: 0 }
+    }
+
+    /// The number of visits counted so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    fn on_visit(&mut self, visit: Visit) -> WalkControl {
+        if (self.pred)(&visit) {
+            self.count += 1;
+        }
+        WalkControl::Continue
+    }
+}
+
+visit_through_on_visit!(impl<F> for Counter<F> where F: FnMut(&Visit) -> bool);
+
+impl<F: FnMut(&Visit) -> bool + Clone + Send> ParallelFsVisitor for Counter<F> {
+    fn fork(&self) -> Self {
+        Self::new(self.pred.clone())
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.count += other.count;
+    }
+}
+
+/// A visitor that collects what a closure returns for every [`Visit`], leaving out
+/// `None`s. With [`walk_dir_parallel`](crate::walk::walk_dir_parallel) the items of
+/// every worker are appended in turn, so they are not in walk order.
+#[derive(Clone)]
+pub struct Collector<T, F> {
+    f: F,
+    items: Vec<T>,
+}
+
+impl<T, F: FnMut(&Visit) -> Option<T>> Collector<T, F> {
+    pub fn new(f: F) -> Self {
+        Self {
+            f,
+            items: Vec::new(),
+        }
+    }
+
+    /// The items collected so far
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+
+    fn on_visit(&mut self, visit: Visit) -> WalkControl {
+        self.items.extend((self.f)(&visit));
+        WalkControl::Continue
+    }
+}
+
+visit_through_on_visit!(impl<T, F> for Collector<T, F> where F: FnMut(&Visit) -> Option<T>);
+
+impl<T: Send, F: FnMut(&Visit) -> Option<T> + Clone + Send> ParallelFsVisitor for Collector<T, F> {
+    fn fork(&self) -> Self {
+        Self::new(self.f.clone())
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.items.extend(other.items);
+    }
+}
+
+/// Adapters for every [`FsVisitor`]
+pub trait FsVisitorExt: FsVisitor + Sized {
+    /// Only pass on the [`Visit`]s `pred` returns `true` for. Leaving out a directory
+    /// doesn't skip its entries, and `pred` sees both its `EnterDir` and its `ExitDir`.
+    fn filter<F>(self, pred: F) -> Filter<Self, F>
+    where
+        F: FnMut(&Visit) -> bool,
+    {
+        Filter { inner: self, pred }
+    }
+
+    /// Pass on every path as rewritten by `f`, including the paths of errors. Handy to
+    /// report paths relative to the root.
+    fn map_paths<F>(self, f: F) -> MapPaths<Self, F>
+    where
+        F: FnMut(&Path) -> PathBuf,
+    {
+        MapPaths { inner: self, f }
+    }
+}
+
+impl<V: FsVisitor> FsVisitorExt for V {}
+
+/// See [`FsVisitorExt::filter`]
+#[derive(Clone)]
+pub struct Filter<V, F> {
+    inner: V,
+    pred: F,
+}
+
+impl<V, F> Filter<V, F> {
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V: FsVisitor, F: FnMut(&Visit) -> bool> FsVisitor for Filter<V, F> {
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        if !(self.pred)(&Visit::File {
+            path,
+            meta: *meta,
+            depth,
+        }) {
+            return WalkControl::Continue;
+        }
+        self.inner.visit_file(path, meta, depth)
+    }
+
+    fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        if !(self.pred)(&Visit::EnterDir {
+            path,
+            meta: *meta,
+            depth,
+        }) {
+            return WalkControl::Continue;
+        }
+        self.inner.enter_dir(path, meta, depth)
+    }
+
+    fn exit_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) {
+        if (self.pred)(&Visit::ExitDir {
+            path,
+            meta: *meta,
+            depth,
+        }) {
+            self.inner.exit_dir(path, meta, depth);
+        }
+    }
+
+    fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+        if (self.pred)(&Visit::Symlink { path, link, depth }) {
+            self.inner.visit_symlink(path, link, depth);
+        }
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        if (self.pred)(&Visit::Error(&error)) {
+            self.inner.on_error(error);
+        }
+    }
+
+    fn skip_mount_point(&mut self, path: &Path, depth: usize) {
+        if (self.pred)(&Visit::MountPoint { path, depth }) {
+            self.inner.skip_mount_point(path, depth);
+        }
+    }
+
+    fn visit_cycle(&mut self, path: &Path, depth: usize) {
+        if (self.pred)(&Visit::Cycle { path, depth }) {
+            self.inner.visit_cycle(path, depth);
+        }
+    }
+
+    fn visit_special(
+        &mut self,
+        path: &Path,
+        meta: &EntryMeta,
+        kind: SpecialKind,
+        depth: usize,
+    ) -> WalkControl {
+        let visit = Visit::Special {
+            path,
+            meta: *meta,
+            kind,
+            depth,
+        };
+        if !(self.pred)(&visit) {
+            return WalkControl::Continue;
+        }
+        self.inner.visit_special(path, meta, kind, depth)
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        self.inner.metadata_needs()
+    }
+}
+
+impl<V, F> ParallelFsVisitor for Filter<V, F>
+where
+    V: ParallelFsVisitor,
+    F: FnMut(&Visit) -> bool + Clone + Send,
+{
+    fn fork(&self) -> Self {
+        Self {
+            inner: self.inner.fork(),
+            pred: self.pred.clone(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.inner.merge(other.inner);
+    }
+}
+
+/// See [`FsVisitorExt::map_paths`]
+#[derive(Clone)]
+pub struct MapPaths<V, F> {
+    inner: V,
+    f: F,
+}
+
+impl<V, F> MapPaths<V, F> {
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V: FsVisitor, F: FnMut(&Path) -> PathBuf> FsVisitor for MapPaths<V, F> {
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        self.inner.visit_file(&(self.f)(path), meta, depth)
+    }
+
+    fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        self.inner.enter_dir(&(self.f)(path), meta, depth)
+    }
+
+    fn exit_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) {
+        self.inner.exit_dir(&(self.f)(path), meta, depth);
+    }
+
+    fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+        self.inner.visit_symlink(&(self.f)(path), link, depth);
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        let error = match error {
+            FsError::Io(path, e) => FsError::Io((self.f)(&path), e),
+            FsError::IgnoreFile(path, e) => FsError::IgnoreFile((self.f)(&path), e),
+            FsError::InvalidPattern(mut err) => {
+                err.file = err.file.map(|file| (self.f)(&file));
+                FsError::InvalidPattern(err)
+            }
+            error @ FsError::RegexError(_) => error,
+        };
+        self.inner.on_error(error);
+    }
+
+    fn skip_mount_point(&mut self, path: &Path, depth: usize) {
+        self.inner.skip_mount_point(&(self.f)(path), depth);
+    }
+
+    fn visit_cycle(&mut self, path: &Path, depth: usize) {
+        self.inner.visit_cycle(&(self.f)(path), depth);
+    }
+
+    fn visit_special(
+        &mut self,
+        path: &Path,
+        meta: &EntryMeta,
+        kind: SpecialKind,
+        depth: usize,
+    ) -> WalkControl {
+        self.inner.visit_special(&(self.f)(path), meta, kind, depth)
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        self.inner.metadata_needs()
+    }
+}
+
+impl<V, F> ParallelFsVisitor for MapPaths<V, F>
+where
+    V: ParallelFsVisitor,
+    F: FnMut(&Path) -> PathBuf + Clone + Send,
+{
+    fn fork(&self) -> Self {
+        Self {
+            inner: self.inner.fork(),
+            f: self.f.clone(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.inner.merge(other.inner);
+    }
+}
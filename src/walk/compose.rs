@@ -0,0 +1,210 @@
+//! Running several visitors in one walk.
+//!
+//! Tuples of visitors, `Vec`s of visitors and boxed visitors are visitors
+//! themselves, so one walk can feed several of them:
+//!
+//! ```no_run
+//! # use fsx::{FindType, FindVisitor, StatsVisitor, WalkOptions, walk_dir_parallel};
+//! # use fsx::filter::GitIgnoreFilter;
+//! # use std::path::Path;
+//! let root = Path::new(".");
+//! let filter = GitIgnoreFilter::new(root, &[]);
+//! let find = FindVisitor::new(r"\.rs$", FindType::File).unwrap();
+//! let mut both = (StatsVisitor::default(), find);
+//! let partial = walk_dir_parallel(root, &mut both, &filter, &WalkOptions::default());
+//! let (stats, find) = both;
+//! let (stats, matches) = (stats.into_report(partial), find.into_report(partial));
+//! ```
+//!
+//! Every visitor gets every callback, in the order the visitors are listed. They
+//! share the walk though: when one of them skips a subtree, it is skipped for all of
+//! them, and when one of them stops, the walk stops. The metadata fetched up front is
+//! whatever the most demanding visitor needs.
+
+use crate::error::FsError;
+use crate::walk::{
+    EntryMeta, FsVisitor, MetadataNeeds, ParallelFsVisitor, SpecialKind, SymlinkInfo, WalkControl,
+};
+use std::path::Path;
+
+impl<V: FsVisitor + ?Sized> FsVisitor for Box<V> {
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        (**self).visit_file(path, meta, depth)
+    }
+
+    fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        (**self).enter_dir(path, meta, depth)
+    }
+
+    fn exit_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) {
+        (**self).exit_dir(path, meta, depth)
+    }
+
+    fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+        (**self).visit_symlink(path, link, depth)
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        (**self).on_error(error)
+    }
+
+    fn skip_mount_point(&mut self, path: &Path, depth: usize) {
+        (**self).skip_mount_point(path, depth)
+    }
+
+    fn visit_cycle(&mut self, path: &Path, depth: usize) {
+        (**self).visit_cycle(path, depth)
+    }
+
+    fn visit_special(
+        &mut self,
+        path: &Path,
+        meta: &EntryMeta,
+        kind: SpecialKind,
+        depth: usize,
+    ) -> WalkControl {
+        (**self).visit_special(path, meta, kind, depth)
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        (**self).metadata_needs()
+    }
+}
+
+impl<V: FsVisitor> FsVisitor for Vec<V> {
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        self.iter_mut()
+            .map(|v| v.visit_file(path, meta, depth))
+            .fold(WalkControl::Continue, Ord::max)
+    }
+
+    fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+        self.iter_mut()
+            .map(|v| v.enter_dir(path, meta, depth))
+            .fold(WalkControl::Continue, Ord::max)
+    }
+
+    fn exit_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) {
+        for v in self {
+            v.exit_dir(path, meta, depth);
+        }
+    }
+
+    fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+        for v in self {
+            v.visit_symlink(path, link, depth);
+        }
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        for v in self {
+            v.on_error(error.clone());
+        }
+    }
+
+    fn skip_mount_point(&mut self, path: &Path, depth: usize) {
+        for v in self {
+            v.skip_mount_point(path, depth);
+        }
+    }
+
+    fn visit_cycle(&mut self, path: &Path, depth: usize) {
+        for v in self {
+            v.visit_cycle(path, depth);
+        }
+    }
+
+    fn visit_special(
+        &mut self,
+        path: &Path,
+        meta: &EntryMeta,
+        kind: SpecialKind,
+        depth: usize,
+    ) -> WalkControl {
+        self.iter_mut()
+            .map(|v| v.visit_special(path, meta, kind, depth))
+            .fold(WalkControl::Continue, Ord::max)
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        self.iter()
+            .map(|v| v.metadata_needs())
+            .fold(MetadataNeeds::FileType, Ord::max)
+    }
+}
+
+impl<V: ParallelFsVisitor> ParallelFsVisitor for Vec<V> {
+    fn fork(&self) -> Self {
+        self.iter().map(|v| v.fork()).collect()
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (v, other) in self.iter_mut().zip(other) {
+            v.merge(other);
+        }
+    }
+}
+
+// Implements `FsVisitor` and `ParallelFsVisitor` for a tuple of visitors, given the
+// type parameter and index of every element
+macro_rules! tuple_visitor {
+    ($($name:ident $idx:tt),*) => {
+        impl<$($name: FsVisitor),*> FsVisitor for ($($name,)*) {
+            fn visit_file(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+                WalkControl::Continue$(.max(self.$idx.visit_file(path, meta, depth)))*
+            }
+
+            fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) -> WalkControl {
+                WalkControl::Continue$(.max(self.$idx.enter_dir(path, meta, depth)))*
+            }
+
+            fn exit_dir(&mut self, path: &Path, meta: &EntryMeta, depth: usize) {
+                $(self.$idx.exit_dir(path, meta, depth);)*
+            }
+
+            fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
+                $(self.$idx.visit_symlink(path, link, depth);)*
+            }
+
+            fn on_error(&mut self, error: FsError) {
+                $(self.$idx.on_error(error.clone());)*
+            }
+
+            fn skip_mount_point(&mut self, path: &Path, depth: usize) {
+                $(self.$idx.skip_mount_point(path, depth);)*
+            }
+
+            fn visit_cycle(&mut self, path: &Path, depth: usize) {
+                $(self.$idx.visit_cycle(path, depth);)*
+            }
+
+            fn visit_special(
+                &mut self,
+                path: &Path,
+                meta: &EntryMeta,
+                kind: SpecialKind,
+                depth: usize,
+            ) -> WalkControl {
+                WalkControl::Continue$(.max(self.$idx.visit_special(path, meta, kind, depth)))*
+            }
+
+            fn metadata_needs(&self) -> MetadataNeeds {
+                MetadataNeeds::FileType$(.max(self.$idx.metadata_needs()))*
+            }
+        }
+
+        impl<$($name: ParallelFsVisitor),*> ParallelFsVisitor for ($($name,)*) {
+            fn fork(&self) -> Self {
+                ($(self.$idx.fork(),)*)
+            }
+
+            fn merge(&mut self, other: Self) {
+                $(self.$idx.merge(other.$idx);)*
+            }
+        }
+    };
+}
+
+tuple_visitor!(A 0, B 1);
+tuple_visitor!(A 0, B 1, C 2);
+tuple_visitor!(A 0, B 1, C 2, D 3);
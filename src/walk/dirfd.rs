@@ -0,0 +1,276 @@
+// Walking through directory file descriptors on Linux, see `WalkOptions::dir_fds`.
+//
+// Directories are listed with `getdents64` and opened relative to their parent with
+// `openat` while the parent is still open, so no path is resolved from the root
+// again. Walks only keep a directory open while they are in it: entries hold a weak
+// handle on the descriptor of their directory, along with the inode they were
+// listed with. Once the directory is closed they are opened by path instead, and
+// checked to still be the directory that was listed.
+//
+// Metadata is handed out as std's `Metadata`, which can't be made from the `stat`
+// that `fstatat` fills in. So it is looked up by the path `/proc/self/fd/N/name`
+// instead: a single `statx`, but the kernel resolves that path through procfs for
+// every entry, and it only works with `/proc` mounted. Without it, the entry is
+// opened with `O_PATH` and `fstat`ed, which takes three calls. The file type, which
+// is all most walks need, comes from `fstatat` when the listing doesn't have it.
+
+use super::walker::{EntryType, SpecialKind};
+use std::{
+    ffi::{CStr, CString, OsStr, OsString},
+    fs::{File, Metadata},
+    io,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, Weak},
+};
+
+// An open directory
+#[derive(Debug)]
+pub(crate) struct DirFd {
+    fd: OwnedFd,
+    // The device the directory is on
+    dev: u64,
+}
+
+// Where an entry lives: its directory, its name in there and its inode as listed
+#[derive(Clone, Debug)]
+pub(crate) struct DirAt {
+    pub(crate) dir: Weak<DirFd>,
+    pub(crate) name: OsString,
+    dev: u64,
+    ino: u64,
+}
+
+impl DirAt {
+    // Open the directory this entry stands for, relative to its parent if that is
+    // still open, otherwise by `path`
+    pub(crate) fn open(&self, path: &Path) -> io::Result<DirFd> {
+        if let Some(dir) = self.dir.upgrade() {
+            return dir.open_at(&self.name);
+        }
+        let dir = DirFd::open(path, false)?;
+        // Mount points are listed with the inode they cover, so only directories
+        // on the same device can be checked
+        if dir.dev == self.dev && dir.ino()? != self.ino {
+            return Err(io::Error::other(
+                "directory was replaced since it was listed",
+            ));
+        }
+        Ok(dir)
+    }
+
+    // The metadata of the entry, relative to its directory if that is still open
+    pub(crate) fn metadata(&self) -> Option<io::Result<Metadata>> {
+        Some(self.dir.upgrade()?.metadata_at(&self.name))
+    }
+}
+
+impl DirFd {
+    // Open the directory at `path`. A symlink at `path` is only followed if
+    // `follow` is set.
+    pub(crate) fn open(path: &Path, follow: bool) -> io::Result<Self> {
+        let path = c_string(path.as_os_str())?;
+        let mut flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        if !follow {
+            flags |= libc::O_NOFOLLOW;
+        }
+        // SAFETY: `path` is a valid NUL terminated string
+        let fd = unsafe { libc::open(path.as_ptr(), flags) };
+        Self::from_raw(fd)
+    }
+
+    // Open the directory `name` inside this one. Fails if `name` has been replaced by
+    // a symlink since it was listed.
+    pub(crate) fn open_at(&self, name: &OsStr) -> io::Result<Self> {
+        let name = c_string(name)?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        // SAFETY: `name` is a valid NUL terminated string and `self.fd` is open
+        let fd = unsafe { libc::openat(self.fd.as_raw_fd(), name.as_ptr(), flags) };
+        Self::from_raw(fd)
+    }
+
+    fn from_raw(fd: libc::c_int) -> io::Result<Self> {
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let dev = fstat(&fd)?.st_dev;
+        Ok(Self { fd, dev })
+    }
+
+    fn ino(&self) -> io::Result<u64> {
+        Ok(fstat(&self.fd)?.st_ino)
+    }
+
+    // Where the entry `name` with inode `ino` of this directory lives
+    pub(crate) fn entry_at(self: &Arc<Self>, name: OsString, ino: u64) -> DirAt {
+        DirAt {
+            dir: Arc::downgrade(self),
+            name,
+            dev: self.dev,
+            ino,
+        }
+    }
+
+    // List the names, inodes and `d_type`s of all entries, except `.` and `..`
+    pub(crate) fn read(&self) -> io::Result<Vec<(OsString, u64, u8)>> {
+        let mut entries = Vec::new();
+        // `u64` elements keep the records aligned
+        let mut buf = vec![0u64; 4096];
+        loop {
+            let len = buf.len() * size_of::<u64>();
+            // SAFETY: `buf` is valid for writes of `len` bytes and `self.fd` is open
+            let read = unsafe {
+                libc::syscall(
+                    libc::SYS_getdents64,
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr(),
+                    len,
+                )
+            };
+            if read < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if read == 0 {
+                return Ok(entries);
+            }
+
+            let bytes = buf.as_ptr().cast::<u8>();
+            let mut offset = 0;
+            while offset < read as usize {
+                // SAFETY: the kernel wrote whole `linux_dirent64` records up to `read`,
+                // each starting with `d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8`
+                // followed by the NUL terminated name
+                let (ino, reclen, d_type, name) = unsafe {
+                    let record = bytes.add(offset);
+                    let ino = record.cast::<u64>().read_unaligned();
+                    let reclen = record.add(16).cast::<u16>().read_unaligned();
+                    let d_type = *record.add(18);
+                    let name = CStr::from_ptr(record.add(19).cast());
+                    (ino, reclen as usize, d_type, name)
+                };
+                offset += reclen;
+
+                let name = name.to_bytes();
+                if name == b"." || name == b".." {
+                    continue;
+                }
+                entries.push((OsString::from_vec(name.to_vec()), ino, d_type));
+            }
+        }
+    }
+
+    // The type of the entry `name` with `d_type` from the listing, `None` for types
+    // that are not walked. Some filesystems don't fill in `d_type`, the type is looked
+    // up then.
+    pub(crate) fn entry_type_at(&self, name: &OsStr, d_type: u8) -> io::Result<Option<EntryType>> {
+        if d_type != libc::DT_UNKNOWN {
+            return Ok(entry_type(d_type));
+        }
+        super::walker::count_stat();
+        let c_name = c_string(name)?;
+        let mut stat = MaybeUninit::<libc::stat>::uninit();
+        // SAFETY: `c_name` is a valid NUL terminated string, `stat` is valid for
+        // writes and `self.fd` is open
+        let res = unsafe {
+            libc::fstatat(
+                self.fd.as_raw_fd(),
+                c_name.as_ptr(),
+                stat.as_mut_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fstatat` succeeded, so it filled in `stat`
+        let mode = unsafe { stat.assume_init() }.st_mode;
+        Ok(entry_type(mode_to_d_type(mode)))
+    }
+
+    // The metadata of `name`, without following it if it is a symlink
+    fn metadata_at(&self, name: &OsStr) -> io::Result<Metadata> {
+        super::walker::count_stat();
+        if proc_fd_available() {
+            let mut path = PathBuf::from(format!("/proc/self/fd/{}", self.fd.as_raw_fd()));
+            path.push(name);
+            return std::fs::symlink_metadata(path);
+        }
+        // Without `/proc` this takes an `O_PATH` descriptor of the entry
+        let name = c_string(name)?;
+        let flags = libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        // SAFETY: `name` is a valid NUL terminated string and `self.fd` is open
+        let fd = unsafe { libc::openat(self.fd.as_raw_fd(), name.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is owned by nobody else
+        File::from(unsafe { OwnedFd::from_raw_fd(fd) }).metadata()
+    }
+}
+
+#[cfg(feature = "test-hooks")]
+thread_local! {
+    // Set by tests to take the fallback without `/proc` on this thread
+    static PROC_FD_DISABLED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+#[cfg(feature = "test-hooks")]
+pub(crate) fn disable_proc_fd(disabled: bool) {
+    PROC_FD_DISABLED.with(|flag| flag.set(disabled));
+}
+
+// Whether descriptors can be looked into through `/proc/self/fd`
+fn proc_fd_available() -> bool {
+    #[cfg(feature = "test-hooks")]
+    if PROC_FD_DISABLED.with(std::cell::Cell::get) {
+        return false;
+    }
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| Path::new("/proc/self/fd").is_dir())
+}
+
+fn fstat(fd: &OwnedFd) -> io::Result<libc::stat> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `stat` is valid for writes and `fd` is open
+    if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fstat` succeeded, so it filled in `stat`
+    Ok(unsafe { stat.assume_init() })
+}
+
+fn c_string(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(io::Error::other)
+}
+
+fn entry_type(d_type: u8) -> Option<EntryType> {
+    match d_type {
+        libc::DT_REG => Some(EntryType::File),
+        libc::DT_DIR => Some(EntryType::Dir),
+        libc::DT_LNK => Some(EntryType::Symlink),
+        libc::DT_FIFO => Some(EntryType::Special(SpecialKind::Fifo)),
+        libc::DT_SOCK => Some(EntryType::Special(SpecialKind::Socket)),
+        libc::DT_CHR => Some(EntryType::Special(SpecialKind::CharDevice)),
+        libc::DT_BLK => Some(EntryType::Special(SpecialKind::BlockDevice)),
+        _ => None,
+    }
+}
+
+fn mode_to_d_type(mode: libc::mode_t) -> u8 {
+    match mode & libc::S_IFMT {
+        libc::S_IFREG => libc::DT_REG,
+        libc::S_IFDIR => libc::DT_DIR,
+        libc::S_IFLNK => libc::DT_LNK,
+        libc::S_IFIFO => libc::DT_FIFO,
+        libc::S_IFSOCK => libc::DT_SOCK,
+        libc::S_IFCHR => libc::DT_CHR,
+        libc::S_IFBLK => libc::DT_BLK,
+        _ => libc::DT_UNKNOWN,
+    }
+}
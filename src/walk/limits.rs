@@ -0,0 +1,151 @@
+use crate::walk::{WalkEntry, WalkEvent, WalkOptions};
+use std::{
+    fmt,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+/// Cancels a walk from another thread, see [`WalkOptions::cancel`].
+///
+/// Clones share the same state, so keep one and hand a clone to the walk.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    // Cancelling the parent cancels this token too, but not the other way round
+    parent: Option<Box<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new token that is also cancelled when this one is. Cancelling it leaves
+    /// this one alone.
+    pub fn child(&self) -> Self {
+        Self {
+            cancelled: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    /// Stop every walk using this token. Callbacks that are already running are
+    /// completed, no new ones are made.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+}
+
+/// What a walk does when it runs into an error
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Don't report errors to the visitor and keep walking
+    Ignore,
+    /// Report every error to the visitor and keep walking
+    #[default]
+    Collect,
+    /// Report the first error to the visitor and stop the walk
+    FailFast,
+}
+
+/// Why a walk ended before visiting the whole tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialReason {
+    /// The [`CancelToken`] was cancelled
+    Cancelled,
+    /// [`WalkOptions::max_entries`] entries were reported
+    MaxEntries,
+    /// [`WalkOptions::deadline`] has passed
+    Deadline,
+    /// An error occurred with [`ErrorPolicy::FailFast`]
+    Error,
+}
+
+impl fmt::Display for PartialReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialReason::Cancelled => write!(f, "walk was cancelled"),
+            PartialReason::MaxEntries => write!(f, "entry limit reached"),
+            PartialReason::Deadline => write!(f, "time limit reached"),
+            PartialReason::Error => write!(f, "stopped at the first error"),
+        }
+    }
+}
+
+// Checks the limits of a walk before every entry and error that is reported. Shared
+// by all workers of a parallel walk.
+pub(crate) struct WalkLimits {
+    error_policy: ErrorPolicy,
+    cancel: Option<CancelToken>,
+    max_entries: usize,
+    deadline: Option<Instant>,
+    reported: AtomicUsize,
+    hit: OnceLock<PartialReason>,
+}
+
+impl WalkLimits {
+    pub(crate) fn new(options: &WalkOptions) -> Self {
+        Self {
+            error_policy: options.error_policy,
+            cancel: options.cancel.clone(),
+            max_entries: options.max_entries.unwrap_or(usize::MAX),
+            deadline: options.deadline,
+            reported: AtomicUsize::new(0),
+            hit: OnceLock::new(),
+        }
+    }
+
+    // Whether `entry` may still be reported. Once a limit is hit, nothing may.
+    // `ExitDir` closes an entry that was already counted, so it isn't counted again.
+    pub(crate) fn allow(&self, entry: &WalkEntry) -> bool {
+        if self.hit.get().is_some() {
+            return false;
+        }
+        let reason = if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            Some(PartialReason::Cancelled)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(PartialReason::Deadline)
+        } else if entry.event() != WalkEvent::ExitDir
+            && self.reported.fetch_add(1, Ordering::SeqCst) >= self.max_entries
+        {
+            Some(PartialReason::MaxEntries)
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => {
+                let _ = self.hit.set(reason);
+                false
+            }
+            None => true,
+        }
+    }
+
+    // Whether an error may be reported, according to the error policy. Failing fast
+    // hits a limit, so only the first error is reported.
+    pub(crate) fn allow_error(&self) -> bool {
+        match self.error_policy {
+            ErrorPolicy::Ignore => false,
+            ErrorPolicy::Collect => self.hit.get().is_none(),
+            ErrorPolicy::FailFast => self.hit.set(PartialReason::Error).is_ok(),
+        }
+    }
+
+    pub(crate) fn partial(&self) -> Option<PartialReason> {
+        self.hit.get().copied()
+    }
+}
@@ -0,0 +1,326 @@
+use super::limits::{PartialReason, WalkLimits};
+use super::progress::ProgressTracker;
+use super::walker::{FileId, WalkRoot, read_entries, resolve_entry, root_id};
+use super::{
+    FsVisitor, WalkControl, WalkEntry, WalkEvent, WalkOptions, WalkStrategy, dispatch,
+    walk_dir_with_options,
+};
+use crate::error::FsError;
+use crate::filter::PathFilter;
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// A visitor that can be split across the worker threads of [`walk_dir_parallel`].
+///
+/// Every worker gets its own fork of the visitor, and the forks are merged back
+/// into the original visitor once the walk has finished.
+///
+/// A fork does not see a nested sequence of `enter_dir` and `exit_dir` calls. The
+/// `exit_dir` of a directory goes to the fork of whichever worker finished the last
+/// listing in its subtree, which is often not the fork that got its `enter_dir`,
+/// and a fork gets the callbacks of unrelated directories in between. State kept
+/// on a stack that is pushed in `enter_dir` and popped in `exit_dir` therefore
+/// breaks. Key such state by path, and combine it in [`merge`](Self::merge).
+pub trait ParallelFsVisitor: FsVisitor + Send + Sized {
+    /// Create an empty visitor for a worker thread
+    fn fork(&self) -> Self;
+    /// Merge the results collected by a worker into `self`
+    fn merge(&mut self, other: Self);
+}
+
+/// Walk `root` using the number of worker threads given in `options`.
+///
+/// Directories are the unit of work: every worker lists one directory at a time and
+/// steals pending directories from the other workers when it runs out of its own.
+/// Each worker reports to its own fork of `visitor`, and the forks are merged into
+/// `visitor` when the walk is done.
+///
+/// Filtering, max depth and symlink cycle detection behave as in [`walk_dir`](super::walk_dir). The
+/// order of callbacks across directories is not deterministic, but `exit_dir` is
+/// still only called once the whole subtree of a directory has been visited, for
+/// either [`WalkStrategy`]. It may go to another fork than the `enter_dir` of the
+/// same directory though, see [`ParallelFsVisitor`]. A breadth first strategy only
+/// makes workers prefer shallow directories.
+/// [`WalkControl::Stop`] from any worker stops all workers, though callbacks that
+/// are already running on other workers are still completed. The same goes for the
+/// limits in `options`, and why the walk ended early is returned if one was hit.
+pub fn walk_dir_parallel<V: ParallelFsVisitor>(
+    root: &Path,
+    visitor: &mut V,
+    filter: &dyn PathFilter,
+    options: &WalkOptions,
+) -> Option<PartialReason> {
+    let workers = options.worker_count();
+    if workers <= 1 {
+        return walk_dir_with_options(root, visitor, filter, options);
+    }
+
+    let shared = SharedWalk {
+        options,
+        max_depth: options.max_depth.unwrap_or(usize::MAX),
+        min_depth: options.min_depth.unwrap_or(0),
+        limits: WalkLimits::new(options),
+        progress: ProgressTracker::new(options.progress.clone(), visitor.metadata_needs()),
+        walk_root: WalkRoot::new(root, options),
+        filter,
+        visited: Mutex::new(root_id(root, options).into_iter().collect()),
+        queues: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+        pending: AtomicUsize::new(0),
+        stopped: AtomicBool::new(false),
+        idle_lock: Mutex::new(()),
+        idle: Condvar::new(),
+    };
+    shared.push(
+        0,
+        DirJob {
+            path: root.to_path_buf(),
+            depth: 1,
+            node: Arc::new(DirNode {
+                dir: None,
+                parent: None,
+                remaining: AtomicUsize::new(1),
+            }),
+        },
+    );
+
+    let forks: Vec<V> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|id| {
+                let mut fork = visitor.fork();
+                let shared = &shared;
+                scope.spawn(move || {
+                    shared.run_worker(id, &mut fork);
+                    fork
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("walk worker panicked"))
+            .collect()
+    });
+
+    for fork in forks {
+        visitor.merge(fork);
+    }
+    shared.progress.finish();
+    shared.limits.partial()
+}
+
+// A directory that has been entered. Tracks how many listings in its subtree are
+// still outstanding so that `exit_dir` can be called once all of them are done.
+struct DirNode {
+    // The entry that entered this directory, `None` for the root
+    dir: Option<WalkEntry>,
+    parent: Option<Arc<DirNode>>,
+    remaining: AtomicUsize,
+}
+
+// A directory whose entries still have to be listed
+struct DirJob {
+    path: PathBuf,
+    depth: usize,
+    node: Arc<DirNode>,
+}
+
+struct SharedWalk<'a> {
+    options: &'a WalkOptions,
+    max_depth: usize,
+    min_depth: usize,
+    limits: WalkLimits,
+    progress: ProgressTracker,
+    walk_root: WalkRoot,
+    filter: &'a dyn PathFilter,
+    visited: Mutex<HashSet<FileId>>,
+    queues: Vec<Mutex<VecDeque<DirJob>>>,
+    pending: AtomicUsize,
+    stopped: AtomicBool,
+    idle_lock: Mutex<()>,
+    idle: Condvar,
+}
+
+impl SharedWalk<'_> {
+    fn push(&self, worker: usize, job: DirJob) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queues[worker].lock().unwrap().push_back(job);
+        self.idle.notify_one();
+    }
+
+    // Take the most recently pushed job from our own queue, or steal the oldest
+    // job from another worker. Old jobs tend to be close to the root, so stealing
+    // them hands out large subtrees. Breadth first walks take the oldest job from
+    // their own queue as well, which keeps the walk roughly level by level.
+    fn pop(&self, worker: usize) -> Option<DirJob> {
+        let own = {
+            let mut queue = self.queues[worker].lock().unwrap();
+            match self.options.strategy {
+                WalkStrategy::DepthFirst => queue.pop_back(),
+                WalkStrategy::BreadthFirst => queue.pop_front(),
+            }
+        };
+        if own.is_some() {
+            return own;
+        }
+        let count = self.queues.len();
+        (1..count).find_map(|offset| {
+            self.queues[(worker + offset) % count]
+                .lock()
+                .unwrap()
+                .pop_front()
+        })
+    }
+
+    // Entries above the minimum depth are walked through but not reported. Once a
+    // limit is hit, all workers are stopped.
+    fn report<V: FsVisitor>(&self, visitor: &mut V, entry: &WalkEntry) -> WalkControl {
+        if entry.depth() < self.min_depth {
+            return WalkControl::Continue;
+        }
+        if !self.limits.allow(entry) {
+            self.stop();
+            return WalkControl::Stop;
+        }
+        match dispatch(visitor, entry) {
+            Ok(control) => {
+                self.progress.entry(entry);
+                control
+            }
+            Err(e) => {
+                self.report_error(visitor, e);
+                WalkControl::Continue
+            }
+        }
+    }
+
+    // Errors are reported according to the error policy, which may stop the walk
+    fn report_error<V: FsVisitor>(&self, visitor: &mut V, error: FsError) {
+        if self.limits.allow_error() {
+            self.progress.error();
+            visitor.on_error(error);
+        }
+        if self.limits.partial().is_some() {
+            self.stop();
+        }
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.idle.notify_all();
+    }
+
+    fn run_worker<V: FsVisitor>(&self, id: usize, visitor: &mut V) {
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            if let Some(job) = self.pop(id) {
+                self.list_dir(id, job, visitor);
+                if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    // That was the last job, wake everyone up so they can exit
+                    self.idle.notify_all();
+                }
+                continue;
+            }
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            // Jobs are still being processed and may produce more work. The timeout
+            // covers wakeups that happen between the checks above and the wait.
+            let guard = self.idle_lock.lock().unwrap();
+            let _ = self
+                .idle
+                .wait_timeout(guard, Duration::from_millis(1))
+                .unwrap();
+        }
+    }
+
+    fn list_dir<V: FsVisitor>(&self, id: usize, job: DirJob, visitor: &mut V) {
+        self.filter.enter_dir(&job.path);
+        match read_entries(&job.path, job.node.dir.as_ref(), job.depth, self.options) {
+            // The directory stays open while its entries are resolved and reported,
+            // subdirectories are opened by path once it is closed
+            Ok((entries, _open)) => {
+                let mut resolved = VecDeque::new();
+                'entries: for raw_res in entries {
+                    if self.stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match raw_res {
+                        Ok(entry) => resolve_entry(
+                            entry,
+                            self.filter,
+                            self.options,
+                            &self.walk_root,
+                            &mut |target| self.visited.lock().unwrap().insert(target),
+                            &mut resolved,
+                        ),
+                        Err(e) => self.report_error(visitor, e),
+                    }
+
+                    while let Some(entry_res) = resolved.pop_front() {
+                        let entry = match entry_res {
+                            Ok(entry) => entry,
+                            Err(e) => {
+                                self.report_error(visitor, e);
+                                continue;
+                            }
+                        };
+
+                        let control = self.report(visitor, &entry);
+                        if control == WalkControl::Stop {
+                            return self.stop();
+                        }
+                        if entry.event() != WalkEvent::EnterDir {
+                            if control == WalkControl::SkipSubtree {
+                                break 'entries;
+                            }
+                            continue;
+                        }
+
+                        if control == WalkControl::Continue && job.depth < self.max_depth {
+                            job.node.remaining.fetch_add(1, Ordering::SeqCst);
+                            let path = entry.path().to_path_buf();
+                            let node = Arc::new(DirNode {
+                                dir: Some(entry),
+                                parent: Some(Arc::clone(&job.node)),
+                                remaining: AtomicUsize::new(1),
+                            });
+                            self.push(
+                                id,
+                                DirJob {
+                                    path,
+                                    depth: job.depth + 1,
+                                    node,
+                                },
+                            );
+                        } else {
+                            self.report(visitor, &entry.into_exit());
+                        }
+                    }
+                }
+            }
+            Err(e) => self.report_error(visitor, e),
+        }
+
+        // Walk up the tree and exit every directory whose subtree is now complete
+        let mut node = Some(job.node);
+        while let Some(current) = node {
+            if current.remaining.fetch_sub(1, Ordering::SeqCst) != 1 {
+                break;
+            }
+            if let Some(dir) = &current.dir {
+                self.filter.exit_dir(dir.path());
+                self.report(visitor, &dir.clone().into_exit());
+            }
+            node = current.parent.clone();
+        }
+    }
+}
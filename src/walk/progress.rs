@@ -0,0 +1,143 @@
+use crate::walk::{MetadataNeeds, WalkEntry, WalkEvent};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// A snapshot of how far a walk has come, passed to a [`ProgressReporter`]
+#[derive(Clone, Debug)]
+pub struct Progress {
+    /// Entries reported to the visitor so far, not counting `exit_dir`
+    pub entries: u64,
+    /// Total size of the files reported so far. `None` when the visitor doesn't need
+    /// the size of files, see [`MetadataNeeds`], as they are not looked up just for
+    /// the progress.
+    pub bytes: Option<u64>,
+    /// Errors reported to the visitor so far
+    pub errors: u64,
+    /// The directory the walk is in, empty in the final report
+    pub current_dir: PathBuf,
+    pub elapsed: Duration,
+    /// Set for the final report, made once the walk has ended
+    pub done: bool,
+}
+
+/// Receives [`Progress`] reports while walking, see [`WalkOptions::progress`].
+///
+/// Reports are throttled to at most one per `interval`, plus a final one once the
+/// walk has ended. With several threads, the callback may be called from any of
+/// them.
+///
+/// [`WalkOptions::progress`]: crate::walk::WalkOptions::progress
+#[derive(Clone)]
+pub struct ProgressReporter {
+    callback: Arc<dyn Fn(&Progress) + Send + Sync>,
+    interval: Duration,
+}
+
+impl ProgressReporter {
+    pub fn new(interval: Duration, callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Arc::new(callback),
+            interval,
+        }
+    }
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+// Counts what a walk reports and passes it on to the reporter, if there is one.
+// Shared by all workers of a parallel walk.
+pub(crate) struct ProgressTracker {
+    reporter: Option<ProgressReporter>,
+    start: Instant,
+    entries: AtomicU64,
+    // Whether the sizes of files are fetched for the visitor, and so counted
+    count_bytes: bool,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+    last_report: Mutex<Instant>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(reporter: Option<ProgressReporter>, needs: MetadataNeeds) -> Self {
+        let start = Instant::now();
+        Self {
+            reporter,
+            start,
+            entries: AtomicU64::new(0),
+            count_bytes: needs >= MetadataNeeds::Size,
+            bytes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            last_report: Mutex::new(start),
+        }
+    }
+
+    // Count an entry after it was reported to the visitor
+    pub(crate) fn entry(&self, entry: &WalkEntry) {
+        let Some(reporter) = &self.reporter else {
+            return;
+        };
+        match entry.event() {
+            WalkEvent::ExitDir => return,
+            WalkEvent::File if self.count_bytes => {
+                // Fetched before the visitor was called
+                if let Some(meta) = entry.cached_metadata() {
+                    self.bytes.fetch_add(meta.len(), Ordering::Relaxed);
+                }
+            }
+            _ => {}
+        }
+        self.entries.fetch_add(1, Ordering::Relaxed);
+
+        let now = Instant::now();
+        // Whoever holds the lock is reporting already, no need to wait for them
+        let Ok(mut last_report) = self.last_report.try_lock() else {
+            return;
+        };
+        if now.duration_since(*last_report) < reporter.interval {
+            return;
+        }
+        *last_report = now;
+        let current_dir = match entry.event() {
+            WalkEvent::EnterDir => entry.path(),
+            _ => entry.path().parent().unwrap_or(Path::new("")),
+        };
+        (reporter.callback)(&self.snapshot(current_dir.to_path_buf(), false));
+    }
+
+    pub(crate) fn error(&self) {
+        if self.reporter.is_some() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Make the final report
+    pub(crate) fn finish(&self) {
+        if let Some(reporter) = &self.reporter {
+            (reporter.callback)(&self.snapshot(PathBuf::new(), true));
+        }
+    }
+
+    fn snapshot(&self, current_dir: PathBuf, done: bool) -> Progress {
+        Progress {
+            entries: self.entries.load(Ordering::Relaxed),
+            bytes: self.count_bytes.then(|| self.bytes.load(Ordering::Relaxed)),
+            errors: self.errors.load(Ordering::Relaxed),
+            current_dir,
+            elapsed: self.start.elapsed(),
+            done,
+        }
+    }
+}
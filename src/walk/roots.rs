@@ -0,0 +1,151 @@
+// Walking several roots so that every entry is visited once, even when roots lie
+// inside each other.
+
+use crate::error::FsError;
+use crate::walk::{
+    EntryMeta, FsVisitor, MetadataNeeds, ParallelFsVisitor, SpecialKind, SymlinkInfo, WalkControl,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// Notes which of a set of roots a walk reached, by any entry below the path the
+// root has in that walk. Entering the root itself doesn't count, as the walk may
+// not go inside, at its max depth for example. Walked along with the visitors of a
+// root that other roots lie inside of.
+pub(crate) struct ReachedRoots {
+    // (index of the root, its path in the walk)
+    targets: Arc<Vec<(usize, PathBuf)>>,
+    reached: Vec<usize>,
+}
+
+impl ReachedRoots {
+    fn new(targets: Vec<(usize, PathBuf)>) -> Self {
+        Self {
+            targets: Arc::new(targets),
+            reached: Vec::new(),
+        }
+    }
+
+    fn saw(&mut self, path: &Path) {
+        for (idx, target) in self.targets.iter() {
+            if path != target && path.starts_with(target) && !self.reached.contains(idx) {
+                self.reached.push(*idx);
+            }
+        }
+    }
+}
+
+impl FsVisitor for ReachedRoots {
+    fn visit_file(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.saw(path);
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.saw(path);
+        WalkControl::Continue
+    }
+
+    fn visit_symlink(&mut self, path: &Path, _link: &SymlinkInfo, _depth: usize) {
+        self.saw(path);
+    }
+
+    fn on_error(&mut self, _error: FsError) {}
+
+    fn skip_mount_point(&mut self, path: &Path, _depth: usize) {
+        self.saw(path);
+    }
+
+    fn visit_cycle(&mut self, path: &Path, _depth: usize) {
+        self.saw(path);
+    }
+
+    fn visit_special(
+        &mut self,
+        path: &Path,
+        _meta: &EntryMeta,
+        _kind: SpecialKind,
+        _depth: usize,
+    ) -> WalkControl {
+        self.saw(path);
+        WalkControl::Continue
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        MetadataNeeds::FileType
+    }
+}
+
+impl ParallelFsVisitor for ReachedRoots {
+    fn fork(&self) -> Self {
+        Self {
+            targets: Arc::clone(&self.targets),
+            reached: Vec::new(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        for idx in other.reached {
+            if !self.reached.contains(&idx) {
+                self.reached.push(idx);
+            }
+        }
+    }
+}
+
+// Calls `walk` with the index of every root that has to be walked so that every
+// entry below `roots` is visited once, and returns the indices of the others.
+//
+// A root that lies inside another root, or is the same directory as a root given
+// before it, is left to the walk of that root. `walk` gets a `ReachedRoots` to walk
+// along and hands it back, and the roots the walk didn't reach, because a filter or
+// a limit kept it away from them, are walked on their own afterwards. `walk` stops
+// the remaining walks by returning `None`.
+pub(crate) fn walk_roots_once(
+    roots: &[&Path],
+    mut walk: impl FnMut(usize, ReachedRoots) -> Option<ReachedRoots>,
+) -> Vec<usize> {
+    let canonical: Vec<_> = roots
+        .iter()
+        .map(|root| fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()))
+        .collect();
+    let mut covered = Vec::new();
+    let mut pending: Vec<usize> = (0..roots.len()).collect();
+    while !pending.is_empty() {
+        let (inner, outer): (Vec<usize>, Vec<usize>) = pending.iter().partition(|&&idx| {
+            pending.iter().any(|&other| {
+                if canonical[other] == canonical[idx] {
+                    other < idx
+                } else {
+                    canonical[idx].starts_with(&canonical[other])
+                }
+            })
+        });
+
+        let mut reached = Vec::new();
+        for &idx in &outer {
+            let mut targets = Vec::new();
+            for &inner_idx in &inner {
+                match canonical[inner_idx].strip_prefix(&canonical[idx]) {
+                    // The same directory is always reached
+                    Ok(rel) if rel.as_os_str().is_empty() => reached.push(inner_idx),
+                    Ok(rel) => targets.push((inner_idx, roots[idx].join(rel))),
+                    Err(_) => {}
+                }
+            }
+            match walk(idx, ReachedRoots::new(targets)) {
+                Some(tracker) => reached.extend(tracker.reached),
+                None => return covered,
+            }
+        }
+
+        let (done, rest): (Vec<usize>, Vec<usize>) =
+            inner.into_iter().partition(|idx| reached.contains(idx));
+        covered.extend(done);
+        pending = rest;
+    }
+    covered
+}
@@ -0,0 +1,138 @@
+use crate::error::FsError;
+use crate::walk::WalkEntry;
+use std::{cmp::Ordering, fmt, sync::Arc, time::SystemTime};
+
+/// Compares two entries of the same directory, see [`SortBy::Custom`]
+pub type EntryComparator = Arc<dyn Fn(&WalkEntry, &WalkEntry) -> Ordering + Send + Sync>;
+
+/// Order in which the entries of a directory are visited
+#[derive(Clone, Default)]
+pub enum SortBy {
+    /// Whatever order the filesystem returns the entries in
+    #[default]
+    None,
+    /// By file name, using [`natural_cmp`] so that `file2` comes before `file10`
+    Name,
+    /// By size, smallest first. Ties are broken by name.
+    Size,
+    /// By modification time, oldest first. Ties are broken by name.
+    Modified,
+    /// By a comparator of your own
+    Custom(EntryComparator),
+}
+
+impl fmt::Debug for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortBy::None => write!(f, "None"),
+            SortBy::Name => write!(f, "Name"),
+            SortBy::Size => write!(f, "Size"),
+            SortBy::Modified => write!(f, "Modified"),
+            SortBy::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// Compare two strings the way a human would, treating runs of digits as numbers.
+///
+/// `file2` sorts before `file10`. Strings that only differ in leading zeros, like
+/// `file01` and `file1`, fall back to plain string order.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        let (x, y) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (*x, *y),
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let x_num = take_number(&mut a_chars);
+            let y_num = take_number(&mut b_chars);
+            // Without leading zeros, a longer run of digits is a larger number
+            let ord = x_num
+                .len()
+                .cmp(&y_num.len())
+                .then_with(|| x_num.cmp(&y_num));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            a_chars.next();
+            b_chars.next();
+        }
+    }
+}
+
+// Consume a run of digits and return it without leading zeros
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        if !(digits.is_empty() && c == '0') {
+            digits.push(c);
+        }
+    }
+    digits
+}
+
+fn by_name(a: &WalkEntry, b: &WalkEntry) -> Ordering {
+    let name = |e: &WalkEntry| {
+        e.path()
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    natural_cmp(&name(a), &name(b))
+}
+
+fn size(entry: &WalkEntry) -> Option<u64> {
+    entry.metadata().ok().map(|m| m.len())
+}
+
+fn modified(entry: &WalkEntry) -> Option<SystemTime> {
+    entry.metadata().ok().and_then(|m| m.modified().ok())
+}
+
+fn compare(sort: &SortBy, a: &WalkEntry, b: &WalkEntry) -> Ordering {
+    match sort {
+        SortBy::None => Ordering::Equal,
+        SortBy::Name => by_name(a, b),
+        SortBy::Size => size(a).cmp(&size(b)).then_with(|| by_name(a, b)),
+        SortBy::Modified => modified(a).cmp(&modified(b)).then_with(|| by_name(a, b)),
+        SortBy::Custom(cmp) => cmp(a, b),
+    }
+}
+
+// Sort the entries of a single directory. Errors are kept in front so they are
+// reported before any of the entries.
+pub(crate) fn sort_entries(
+    entries: &mut [Result<WalkEntry, FsError>],
+    sort: &SortBy,
+    reverse: bool,
+) {
+    match sort {
+        SortBy::None => return,
+        // Fetch metadata once up front instead of during the comparisons
+        SortBy::Size | SortBy::Modified => {
+            for entry in entries.iter().flatten() {
+                let _ = entry.metadata();
+            }
+        }
+        SortBy::Name | SortBy::Custom(_) => {}
+    }
+
+    entries.sort_by(|a, b| match (a, b) {
+        (Ok(a), Ok(b)) => {
+            let ord = compare(sort, a, b);
+            if reverse { ord.reverse() } else { ord }
+        }
+        (Err(_), Err(_)) => Ordering::Equal,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Ok(_), Err(_)) => Ordering::Greater,
+    });
+}
@@ -0,0 +1,872 @@
+use crate::error::FsError;
+use crate::filter::PathFilter;
+#[cfg(target_os = "linux")]
+use crate::walk::dirfd::{DirAt, DirFd};
+use crate::walk::limits::{PartialReason, WalkLimits};
+use crate::walk::sort::sort_entries;
+use crate::walk::{WalkOptions, WalkStrategy};
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, FileType, Metadata},
+    io,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+};
+
+#[cfg(feature = "test-hooks")]
+thread_local! {
+    // Metadata lookups made by walks on this thread, see `stat_calls`
+    static STAT_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// Called for every metadata lookup of the walker, so that tests can count them
+pub(crate) fn count_stat() {
+    #[cfg(feature = "test-hooks")]
+    STAT_CALLS.with(|calls| calls.set(calls.get() + 1));
+}
+
+// The number of metadata lookups walks have made on the current thread so far
+#[cfg(feature = "test-hooks")]
+pub(crate) fn stat_calls() -> usize {
+    STAT_CALLS.with(std::cell::Cell::get)
+}
+
+fn stat(path: &Path, follow_symlinks: bool) -> io::Result<Metadata> {
+    count_stat();
+    if follow_symlinks {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    }
+}
+
+/// What a [`WalkEntry`] stands for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkEvent {
+    /// A regular file, or the target of a followed symlink to a file
+    File,
+    /// A symbolic link. When following symlinks, the target is yielded right after it.
+    Symlink,
+    /// A directory is entered. Its entries follow, then a matching `ExitDir`.
+    EnterDir,
+    /// All entries of a directory have been yielded
+    ExitDir,
+    /// A directory on another filesystem than the root, which is not entered because
+    /// [`WalkOptions::one_file_system`] is set
+    MountPoint,
+    /// A directory, or the target of a followed symlink, that has already been visited.
+    /// Most often a symlink pointing back to one of its ancestors. It is not visited
+    /// again. The path is the one it was reached through this time.
+    Cycle,
+    /// A file that is neither a regular file, a directory nor a symlink, or the target
+    /// of a followed symlink to one
+    Special(SpecialKind),
+}
+
+/// Kinds of special files, which only exist on Unix
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecialKind {
+    /// A named pipe
+    Fifo,
+    /// A Unix domain socket
+    Socket,
+    /// A character device, like `/dev/null`
+    CharDevice,
+    /// A block device, like a disk
+    BlockDevice,
+}
+
+impl SpecialKind {
+    /// The kind of special file `file_type` stands for, `None` for regular files,
+    /// directories and symlinks
+    #[cfg(unix)]
+    pub fn from_file_type(file_type: &FileType) -> Option<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            Some(SpecialKind::Fifo)
+        } else if file_type.is_socket() {
+            Some(SpecialKind::Socket)
+        } else if file_type.is_char_device() {
+            Some(SpecialKind::CharDevice)
+        } else if file_type.is_block_device() {
+            Some(SpecialKind::BlockDevice)
+        } else {
+            None
+        }
+    }
+
+    /// The kind of special file `file_type` stands for, `None` for regular files,
+    /// directories and symlinks
+    #[cfg(not(unix))]
+    pub fn from_file_type(_file_type: &FileType) -> Option<Self> {
+        None
+    }
+}
+
+/// The type of an entry. Unlike [`FileType`] it is known from a directory listing
+/// alone, without looking up the metadata of the entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Special(SpecialKind),
+}
+
+impl EntryType {
+    /// The type `file_type` stands for, `None` for types that are not walked
+    pub fn from_file_type(file_type: &FileType) -> Option<Self> {
+        if file_type.is_symlink() {
+            Some(EntryType::Symlink)
+        } else if file_type.is_dir() {
+            Some(EntryType::Dir)
+        } else if file_type.is_file() {
+            Some(EntryType::File)
+        } else {
+            SpecialKind::from_file_type(file_type).map(EntryType::Special)
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        *self == EntryType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        *self == EntryType::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        *self == EntryType::Symlink
+    }
+
+    // The event an entry of this type is first yielded with
+    fn event(self) -> WalkEvent {
+        match self {
+            EntryType::File => WalkEvent::File,
+            EntryType::Dir => WalkEvent::EnterDir,
+            EntryType::Symlink => WalkEvent::Symlink,
+            EntryType::Special(kind) => WalkEvent::Special(kind),
+        }
+    }
+}
+
+/// A single entry yielded by a [`Walker`]
+#[derive(Clone, Debug)]
+pub struct WalkEntry {
+    path: PathBuf,
+    file_type: EntryType,
+    depth: usize,
+    event: WalkEvent,
+    meta: OnceLock<Metadata>,
+    link: Option<Box<SymlinkInfo>>,
+    // The directory the entry was listed from, with `WalkOptions::dir_fds`
+    #[cfg(target_os = "linux")]
+    at: Option<DirAt>,
+}
+
+/// Where a symlink points to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymlinkInfo {
+    /// The target exactly as stored in the link, see [`fs::read_link`]
+    pub target: PathBuf,
+    /// Whether the target exists. `false` for dangling links.
+    pub resolves: bool,
+    /// Whether the target lies inside the walked root. For dangling links this is
+    /// decided on the path alone.
+    pub inside_root: bool,
+}
+
+impl WalkEntry {
+    fn new(path: PathBuf, file_type: EntryType, depth: usize) -> Self {
+        Self {
+            path,
+            file_type,
+            depth,
+            event: file_type.event(),
+            meta: OnceLock::new(),
+            link: None,
+            #[cfg(target_os = "linux")]
+            at: None,
+        }
+    }
+
+    fn with_metadata(
+        path: PathBuf,
+        meta: Metadata,
+        file_type: EntryType,
+        depth: usize,
+        event: WalkEvent,
+    ) -> Self {
+        Self {
+            path,
+            file_type,
+            depth,
+            event,
+            meta: OnceLock::from(meta),
+            link: None,
+            #[cfg(target_os = "linux")]
+            at: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// Type of the entry, without following symlinks
+    pub fn file_type(&self) -> EntryType {
+        self.file_type
+    }
+
+    /// Depth of the entry. Entries directly under the root have depth 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn event(&self) -> WalkEvent {
+        self.event
+    }
+
+    /// Where the link points to, for [`WalkEvent::Symlink`] entries
+    pub fn symlink(&self) -> Option<&SymlinkInfo> {
+        self.link.as_deref()
+    }
+
+    /// The path of the entry with all symlinks resolved. Only differs from
+    /// [`path`](Self::path) below followed symlinks with
+    /// [`WalkOptions::logical_paths`] set.
+    pub fn physical_path(&self) -> Result<PathBuf, FsError> {
+        fs::canonicalize(&self.path).map_err(|e| FsError::Io(self.path.clone(), e))
+    }
+
+    /// Metadata of the entry, fetched on first use.
+    ///
+    /// Symlinks themselves are not followed. Targets of followed symlinks are
+    /// yielded as entries of their own and carry the metadata of the target.
+    pub fn metadata(&self) -> Result<&Metadata, FsError> {
+        if let Some(meta) = self.meta.get() {
+            return Ok(meta);
+        }
+        let meta = self
+            .lookup_metadata()
+            .map_err(|e| FsError::Io(self.path.clone(), e))?;
+        Ok(self.meta.get_or_init(|| meta))
+    }
+
+    // Look up the metadata relative to the directory of the entry if that is open
+    fn lookup_metadata(&self) -> io::Result<Metadata> {
+        #[cfg(target_os = "linux")]
+        if let Some(meta) = self.at.as_ref().and_then(DirAt::metadata) {
+            return meta;
+        }
+        stat(&self.path, false)
+    }
+
+    // The metadata if it was fetched already
+    pub(crate) fn cached_metadata(&self) -> Option<&Metadata> {
+        self.meta.get()
+    }
+}
+
+/// Metadata of an entry as passed to [`FsVisitor`](crate::walk::FsVisitor) callbacks.
+///
+/// The file type is always known. What else was fetched before the callback depends
+/// on [`FsVisitor::metadata_needs`](crate::walk::FsVisitor::metadata_needs), anything
+/// more is fetched on first use.
+#[derive(Clone, Copy, Debug)]
+pub struct EntryMeta<'a> {
+    entry: &'a WalkEntry,
+}
+
+impl<'a> EntryMeta<'a> {
+    pub(crate) fn new(entry: &'a WalkEntry) -> Self {
+        Self { entry }
+    }
+
+    /// Type of the entry, or of the target for followed symlinks
+    pub fn file_type(&self) -> EntryType {
+        self.entry.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.entry.file_type.is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.entry.file_type.is_file()
+    }
+
+    /// Size in bytes, see [`Metadata::len`]
+    pub fn size(&self) -> Result<u64, FsError> {
+        self.metadata().map(Metadata::len)
+    }
+
+    /// All metadata, see [`WalkEntry::metadata`]
+    pub fn metadata(&self) -> Result<&'a Metadata, FsError> {
+        self.entry.metadata()
+    }
+
+    /// The metadata if it was fetched already, without fetching it
+    pub fn cached(&self) -> Option<&'a Metadata> {
+        self.entry.cached_metadata()
+    }
+}
+
+// With `WalkOptions::dir_fds`, keeps a listed directory open so that its
+// subdirectories are opened relative to it. Walks hold on to it only while they are
+// in the directory, which bounds the open descriptors by the depth of the walk.
+#[derive(Default)]
+pub(crate) struct OpenDir {
+    #[cfg(target_os = "linux")]
+    _fd: Option<Arc<DirFd>>,
+}
+
+// The entries of a directory, and the directory itself while it is open
+type Listing = (Vec<Result<WalkEntry, FsError>>, OpenDir);
+
+// Read all entries of `dir` that sit at `depth`, sorted as requested in `options`.
+// `parent` is the entry `dir` was entered through, `None` for the root. Errors for
+// single entries are returned in place. The entries are not filtered yet and
+// directories have not been checked for cycles.
+pub(crate) fn read_entries(
+    dir: &Path,
+    parent: Option<&WalkEntry>,
+    depth: usize,
+    options: &WalkOptions,
+) -> Result<Listing, FsError> {
+    let (mut entries, open) =
+        list_dir(dir, parent, depth, options).map_err(|e| FsError::Io(dir.to_path_buf(), e))?;
+    sort_entries(&mut entries, &options.sort, options.sort_reverse);
+    Ok((entries, open))
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn list_dir(
+    dir: &Path,
+    parent: Option<&WalkEntry>,
+    depth: usize,
+    options: &WalkOptions,
+) -> io::Result<Listing> {
+    #[cfg(target_os = "linux")]
+    if options.dir_fds {
+        return list_dir_at(dir, parent, depth);
+    }
+
+    let entries = fs::read_dir(dir)?
+        .filter_map(|entry_res| {
+            // We don't hold on to `fs::DirEntry` since it keeps the directory handle open
+            let entry = match entry_res {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(FsError::Io(dir.to_path_buf(), e))),
+            };
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(ft) => EntryType::from_file_type(&ft)?,
+                Err(e) => return Some(Err(FsError::Io(path, e))),
+            };
+            Some(Ok(WalkEntry::new(path, file_type, depth)))
+        })
+        .collect();
+    Ok((entries, OpenDir::default()))
+}
+
+// Like `list_dir`, through the descriptor of the directory. Directories that were
+// listed themselves are opened relative to their parent while it is open, the root
+// and targets of followed symlinks by their path.
+#[cfg(target_os = "linux")]
+fn list_dir_at(dir: &Path, parent: Option<&WalkEntry>, depth: usize) -> io::Result<Listing> {
+    let fd = match parent.and_then(|parent| parent.at.as_ref()) {
+        Some(at) => at.open(dir)?,
+        None => DirFd::open(dir, true)?,
+    };
+    let fd = Arc::new(fd);
+    let entries = fd
+        .read()?
+        .into_iter()
+        .filter_map(|(name, ino, d_type)| {
+            let path = dir.join(&name);
+            let file_type = match fd.entry_type_at(&name, d_type) {
+                Ok(file_type) => file_type?,
+                Err(e) => return Some(Err(FsError::Io(path, e))),
+            };
+            Some(Ok(WalkEntry {
+                at: Some(fd.entry_at(name, ino)),
+                ..WalkEntry::new(path, file_type, depth)
+            }))
+        })
+        .collect();
+    Ok((entries, OpenDir { _fd: Some(fd) }))
+}
+
+// What entries are compared against to tell whether they are still on the root's
+// filesystem and whether symlinks point inside the root
+#[derive(Default)]
+pub(crate) struct WalkRoot {
+    // The root with all symlinks resolved, or as given if that fails
+    path: PathBuf,
+    // The device of the root, if the walk should stay on it
+    dev: Option<u64>,
+}
+
+impl WalkRoot {
+    pub(crate) fn new(root: &Path, options: &WalkOptions) -> Self {
+        let dev = if options.one_file_system {
+            stat(root, true).ok().and_then(|meta| device_id(&meta))
+        } else {
+            None
+        };
+        Self {
+            path: fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()),
+            dev,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn device_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
+
+// Device ids are not available through std on other platforms
+#[cfg(not(unix))]
+fn device_id(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+// Identifies a file independent of the path it was reached through
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum FileId {
+    #[cfg(unix)]
+    Inode { dev: u64, ino: u64 },
+    // Inode numbers are not available through std on other platforms
+    #[cfg(not(unix))]
+    Path(PathBuf),
+}
+
+#[cfg(unix)]
+fn file_id(_path: &Path, meta: &Metadata) -> Result<FileId, FsError> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(FileId::Inode {
+        dev: meta.dev(),
+        ino: meta.ino(),
+    })
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _meta: &Metadata) -> Result<FileId, FsError> {
+    fs::canonicalize(path)
+        .map(FileId::Path)
+        .map_err(|e| FsError::Io(path.to_path_buf(), e))
+}
+
+// The id of `root`, so that symlinks back to it are caught as cycles. Only needed
+// when following symlinks.
+pub(crate) fn root_id(root: &Path, options: &WalkOptions) -> Option<FileId> {
+    if !options.follow_symlinks {
+        return None;
+    }
+    let meta = stat(root, true).ok()?;
+    file_id(root, &meta).ok()
+}
+
+// Read where the symlink at `path` points to. `target` is the resolved target, if
+// it exists.
+fn symlink_info(
+    path: &Path,
+    target: Option<&Path>,
+    root: &WalkRoot,
+) -> Result<SymlinkInfo, FsError> {
+    let raw = fs::read_link(path).map_err(|e| FsError::Io(path.to_path_buf(), e))?;
+    let inside_root = match target {
+        Some(target) => target.starts_with(&root.path),
+        // Resolve dangling links against their parent, without touching the filesystem
+        None => {
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let parent = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+            normalize_lexically(&parent.join(&raw)).starts_with(&root.path)
+        }
+    };
+    Ok(SymlinkInfo {
+        target: raw,
+        resolves: target.is_some(),
+        inside_root,
+    })
+}
+
+// Remove `.` and `..` components from `path` without following any symlinks
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// A directory is entered unless it lives on another device than the root
+fn enter_or_skip(entry: WalkEntry, root: &WalkRoot) -> WalkEntry {
+    let dev = entry.meta.get().and_then(device_id);
+    match (root.dev, dev) {
+        (Some(root_dev), Some(dev)) if root_dev != dev => WalkEntry {
+            event: WalkEvent::MountPoint,
+            ..entry
+        },
+        _ => entry,
+    }
+}
+
+// Filter `entry` and turn it into the entries it produces, in order. A followed
+// symlink produces the link itself and then its target. `first_visit` records a
+// file as visited and returns false if it already was.
+pub(crate) fn resolve_entry(
+    entry: WalkEntry,
+    filter: &dyn PathFilter,
+    options: &WalkOptions,
+    root: &WalkRoot,
+    first_visit: &mut dyn FnMut(FileId) -> bool,
+    out: &mut VecDeque<Result<WalkEntry, FsError>>,
+) {
+    let depth = entry.depth;
+    if filter.is_ignored(&entry.path, entry.file_type.is_dir()) {
+        return;
+    }
+
+    match entry.event {
+        WalkEvent::Symlink => {
+            let path = entry.path.clone();
+            let target_meta = stat(&path, true);
+            let target = target_meta
+                .as_ref()
+                .ok()
+                .and_then(|_| fs::canonicalize(&path).ok());
+            let link = match symlink_info(&path, target.as_deref(), root) {
+                Ok(link) => link,
+                Err(e) => {
+                    out.push_back(Err(e));
+                    return;
+                }
+            };
+            out.push_back(Ok(WalkEntry {
+                link: Some(Box::new(link)),
+                ..entry
+            }));
+            if !options.follow_symlinks {
+                return;
+            }
+
+            let target_meta = match target_meta {
+                Ok(m) => m,
+                // Dangling links are reported through `SymlinkInfo::resolves`
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+                Err(e) => {
+                    out.push_back(Err(FsError::Io(path, e)));
+                    return;
+                }
+            };
+            let Some(target_type) = EntryType::from_file_type(&target_meta.file_type()) else {
+                return;
+            };
+            match file_id(&path, &target_meta).map(&mut *first_visit) {
+                Ok(true) => {}
+                Ok(false) => {
+                    out.push_back(Ok(WalkEntry::with_metadata(
+                        path,
+                        target_meta,
+                        target_type,
+                        depth,
+                        WalkEvent::Cycle,
+                    )));
+                    return;
+                }
+                Err(e) => {
+                    out.push_back(Err(e));
+                    return;
+                }
+            }
+            let target = if options.logical_paths {
+                path
+            } else if let Some(target) = target {
+                target
+            } else {
+                let e = io::Error::other("symlink target could not be resolved");
+                out.push_back(Err(FsError::Io(path, e)));
+                return;
+            };
+
+            // Make sure that we don't visit ignored symlink targets
+            if filter.is_ignored(&target, target_type.is_dir()) {
+                return;
+            }
+
+            let event = target_type.event();
+            let target = WalkEntry::with_metadata(target, target_meta, target_type, depth, event);
+            out.push_back(Ok(enter_or_skip(target, root)));
+        }
+        // Cycles and mount points can only be told apart by metadata. Otherwise the
+        // file type from the listing is enough to enter a directory.
+        WalkEvent::EnterDir if options.follow_symlinks || options.one_file_system => {
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    out.push_back(Err(e));
+                    return;
+                }
+            };
+            if options.follow_symlinks {
+                match file_id(&entry.path, meta).map(&mut *first_visit) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        out.push_back(Ok(WalkEntry {
+                            event: WalkEvent::Cycle,
+                            ..entry
+                        }));
+                        return;
+                    }
+                    Err(e) => {
+                        out.push_back(Err(e));
+                        return;
+                    }
+                }
+            }
+            out.push_back(Ok(enter_or_skip(entry, root)));
+        }
+        _ => out.push_back(Ok(entry)),
+    }
+}
+
+// An open directory on the walker's stack
+struct DirFrame {
+    // The entry that entered this directory, `None` for the root
+    dir: Option<WalkEntry>,
+    entries: std::vec::IntoIter<Result<WalkEntry, FsError>>,
+    _open: OpenDir,
+}
+
+/// A pull based directory walker.
+///
+/// Yields the same entries, in the same order, as [`walk_dir`](crate::walk_dir)
+/// reports them to a visitor, with filtering, max depth and symlink cycle detection
+/// behaving the same way. Directories are tracked on an explicit stack rather than
+/// through recursion, so arbitrarily deep trees can be walked.
+///
+/// With [`WalkStrategy::BreadthFirst`] directories are queued when they are found
+/// and only entered once everything before them in the queue has been walked. An
+/// `EnterDir` is then followed by the direct entries of that directory and its
+/// `ExitDir`, while its subdirectories go to the back of the queue.
+///
+/// The root itself is not yielded. Errors are yielded in place and the walk carries
+/// on after them.
+pub struct Walker<'a> {
+    filter: &'a dyn PathFilter,
+    options: WalkOptions,
+    max_depth: usize,
+    min_depth: usize,
+    limits: WalkLimits,
+    walk_root: WalkRoot,
+    visited: HashSet<FileId>,
+    stack: Vec<DirFrame>,
+    // Directories waiting to be entered in a breadth first walk
+    queued: VecDeque<WalkEntry>,
+    // Entries that have been resolved but not yet yielded
+    pending: VecDeque<Result<WalkEntry, FsError>>,
+    // The directory yielded last by `EnterDir`, descended into on the next call
+    entered: Option<WalkEntry>,
+    skip_entered: bool,
+    // Fetch the metadata of directories before they are yielded, so that their
+    // `EnterDir` and `ExitDir` entries share it
+    stat_dirs: bool,
+    root: Option<PathBuf>,
+}
+
+impl<'a> Walker<'a> {
+    pub fn new(root: &Path, filter: &'a dyn PathFilter, options: &WalkOptions) -> Self {
+        Self {
+            filter,
+            options: options.clone(),
+            max_depth: options.max_depth.unwrap_or(usize::MAX),
+            min_depth: options.min_depth.unwrap_or(0),
+            limits: WalkLimits::new(options),
+            walk_root: WalkRoot::default(),
+            visited: HashSet::new(),
+            stack: Vec::new(),
+            queued: VecDeque::new(),
+            pending: VecDeque::new(),
+            entered: None,
+            skip_entered: false,
+            stat_dirs: false,
+            root: Some(root.to_path_buf()),
+        }
+    }
+
+    /// Skip the rest of the current directory.
+    ///
+    /// Right after an `EnterDir` entry this skips that directory: none of its entries
+    /// are yielded, but its `ExitDir` still is. Otherwise the remaining entries of the
+    /// directory containing the last yielded entry are skipped.
+    pub fn skip_current_dir(&mut self) {
+        if self.entered.is_some() {
+            self.skip_entered = true;
+        } else if let Some(frame) = self.stack.last_mut() {
+            self.pending.clear();
+            frame.entries = Vec::new().into_iter();
+        }
+    }
+
+    /// Why the walk ended early, if one of the limits in the [`WalkOptions`] was hit.
+    /// The walker yields nothing more once it was.
+    pub fn partial(&self) -> Option<PartialReason> {
+        self.limits.partial()
+    }
+
+    // Whether an error that came up outside of the walker may be reported, see
+    // `WalkLimits::allow_error`
+    pub(super) fn allow_error(&self) -> bool {
+        self.limits.allow_error()
+    }
+
+    // Used when the visitor needs the metadata of every directory anyway
+    pub(super) fn stat_dirs(&mut self) {
+        self.stat_dirs = true;
+    }
+
+    // Remember `dir` to descend into it on the next call
+    fn enter(&mut self, dir: &WalkEntry) {
+        if self.stat_dirs {
+            // Errors are reported when the metadata is asked for again
+            let _ = dir.metadata();
+        }
+        self.entered = Some(dir.clone());
+    }
+
+    // Push a frame for `dir`, or yield an error if it can't be read
+    fn open_dir(&mut self, path: &Path, dir: Option<WalkEntry>, depth: usize) -> Option<FsError> {
+        self.filter.enter_dir(path);
+        let ((entries, open), error) = match read_entries(path, dir.as_ref(), depth, &self.options)
+        {
+            Ok(listing) => (listing, None),
+            Err(e) => ((Vec::new(), OpenDir::default()), Some(e)),
+        };
+        self.stack.push(DirFrame {
+            dir,
+            entries: entries.into_iter(),
+            _open: open,
+        });
+        error
+    }
+}
+
+impl Iterator for Walker<'_> {
+    type Item = Result<WalkEntry, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.limits.partial().is_some() {
+            return None;
+        }
+        loop {
+            let next = self.next_entry()?;
+            // Entries above the minimum depth are walked through but not yielded
+            match &next {
+                Ok(entry) if entry.depth < self.min_depth => continue,
+                Ok(entry) if !self.limits.allow(entry) => return None,
+                Err(_) if !self.limits.allow_error() => {
+                    if self.limits.partial().is_some() {
+                        return None;
+                    }
+                }
+                _ => return Some(next),
+            }
+        }
+    }
+}
+
+impl Walker<'_> {
+    fn next_entry(&mut self) -> Option<Result<WalkEntry, FsError>> {
+        if let Some(root) = self.root.take() {
+            self.walk_root = WalkRoot::new(&root, &self.options);
+            self.visited.extend(root_id(&root, &self.options));
+            let root_error = self.open_dir(&root, None, 1);
+            if let Some(e) = root_error {
+                return Some(Err(e));
+            }
+        }
+
+        // Descend into the directory we yielded last, unless it was skipped or is
+        // already at max depth. If we don't descend, exit it right away.
+        if let Some(dir) = self.entered.take() {
+            let skip = std::mem::take(&mut self.skip_entered);
+            if skip || dir.depth >= self.max_depth {
+                return Some(Ok(dir.into_exit()));
+            }
+            let path = dir.path.clone();
+            let depth = dir.depth + 1;
+            if let Some(e) = self.open_dir(&path, Some(dir), depth) {
+                return Some(Err(e));
+            }
+        }
+
+        loop {
+            if let Some(next) = self.pending.pop_front() {
+                match next {
+                    Ok(entry) if entry.event == WalkEvent::EnterDir => {
+                        if self.options.strategy == WalkStrategy::BreadthFirst {
+                            self.queued.push_back(entry);
+                            continue;
+                        }
+                        self.enter(&entry);
+                        return Some(Ok(entry));
+                    }
+                    next => return Some(next),
+                }
+            }
+
+            let Some(frame) = self.stack.last_mut() else {
+                // Only breadth first walks queue directories
+                let dir = self.queued.pop_front()?;
+                self.enter(&dir);
+                return Some(Ok(dir));
+            };
+            match frame.entries.next() {
+                Some(Ok(entry)) => {
+                    let visited = &mut self.visited;
+                    resolve_entry(
+                        entry,
+                        self.filter,
+                        &self.options,
+                        &self.walk_root,
+                        &mut |target| visited.insert(target),
+                        &mut self.pending,
+                    );
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let frame = self.stack.pop().expect("frame was just inspected");
+                    if let Some(dir) = frame.dir {
+                        self.filter.exit_dir(&dir.path);
+                        return Some(Ok(dir.into_exit()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl WalkEntry {
+    pub(super) fn into_exit(self) -> Self {
+        Self {
+            event: WalkEvent::ExitDir,
+            ..self
+        }
+    }
+}
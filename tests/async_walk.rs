@@ -0,0 +1,208 @@
+#![cfg(feature = "async")]
+
+use fsx::test_utils::{FsNode, create_fs_tree};
+use fsx::{
+    CancelToken, PartialReason, PathFilter, SortBy, WalkEvent, WalkOptions, Walker, async_walk,
+    collect_with_options, find_with_options,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tempfile::tempdir;
+use tokio_stream::StreamExt;
+
+// Counts the entries the walk has looked at, optionally taking its time for each
+#[derive(Clone, Default)]
+struct CountingFilter {
+    seen: Arc<AtomicUsize>,
+    delay: Option<Duration>,
+}
+
+impl PathFilter for CountingFilter {
+    fn is_ignored(&self, _path: &Path, _is_dir: bool) -> bool {
+        self.seen.fetch_add(1, Ordering::SeqCst);
+        if let Some(delay) = self.delay {
+            std::thread::sleep(delay);
+        }
+        false
+    }
+}
+
+fn small_tree() -> FsNode<'static> {
+    FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"),
+            FsNode::Dir(
+                "a",
+                vec![
+                    FsNode::File("a1.txt", "a"),
+                    FsNode::Dir("a2", vec![FsNode::File("a3.log", "a")]),
+                ],
+            ),
+            FsNode::Dir("b", vec![FsNode::File("b1.txt", "b")]),
+        ],
+    )
+}
+
+// A directory with `count` files in it
+fn create_many_files(root: &Path, count: usize) {
+    std::fs::create_dir(root).unwrap();
+    for i in 0..count {
+        std::fs::write(root.join(format!("file{i}.txt")), "x").unwrap();
+    }
+}
+
+// Waits until the walk behind `filter` has stopped looking at entries, and returns
+// how many it looked at
+async fn settled(filter: &CountingFilter) -> usize {
+    let mut last = filter.seen.load(Ordering::SeqCst);
+    loop {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let seen = filter.seen.load(Ordering::SeqCst);
+        if seen == last {
+            return seen;
+        }
+        last = seen;
+    }
+}
+
+#[tokio::test]
+async fn stream_yields_what_the_walker_yields() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &small_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let filter = CountingFilter::default();
+    let expected: Vec<(PathBuf, WalkEvent)> = Walker::new(&root, &filter, &options)
+        .map(|entry| entry.unwrap())
+        .map(|entry| (entry.path().to_path_buf(), entry.event()))
+        .collect();
+
+    let mut stream = async_walk::walk(&root, filter, options);
+    let mut entries = Vec::new();
+    while let Some(entry) = stream.next().await {
+        let entry = entry.unwrap();
+        entries.push((entry.path().to_path_buf(), entry.event()));
+    }
+
+    assert_eq!(entries, expected);
+    assert_eq!(stream.partial(), None);
+}
+
+#[tokio::test]
+async fn stream_reports_limits() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &small_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        max_entries: Some(2),
+        ..Default::default()
+    };
+
+    let filter = CountingFilter::default();
+    let expected = Walker::new(&root, &filter, &options).count();
+
+    let mut stream = async_walk::walk(&root, filter, options);
+    let mut count = 0;
+    while stream.next().await.is_some() {
+        count += 1;
+    }
+
+    assert_eq!(count, expected);
+    assert_eq!(stream.partial(), Some(PartialReason::MaxEntries));
+}
+
+#[tokio::test]
+async fn async_collect_and_find_match_the_blocking_ones() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &small_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions::default();
+    let filter = CountingFilter::default();
+
+    let expected = collect_with_options(&root, &filter, &options);
+    let report = async_walk::collect(&root, filter.clone(), options.clone()).await;
+    assert_eq!(report.stats.total_files, expected.stats.total_files);
+    assert_eq!(report.stats.total_dirs, expected.stats.total_dirs);
+    assert_eq!(report.stats.total_size, expected.stats.total_size);
+    assert_eq!(report.partial, None);
+
+    let expected = find_with_options(&root, &filter, r"\.txt$", &options);
+    let report = async_walk::find(&root, filter, r"\.txt$", options).await;
+    let mut found: Vec<_> = report.entries.into_iter().map(|e| e.name).collect();
+    let mut expected: Vec<_> = expected.entries.into_iter().map(|e| e.name).collect();
+    found.sort();
+    expected.sort();
+    assert_eq!(found.len(), 3);
+    assert_eq!(found, expected);
+}
+
+#[tokio::test]
+async fn dropping_the_stream_stops_the_walk() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path().join("root");
+    create_many_files(&root, 5000);
+
+    let filter = CountingFilter::default();
+    let mut stream = async_walk::walk(&root, filter.clone(), WalkOptions::default());
+    for _ in 0..10 {
+        stream.next().await.unwrap().unwrap();
+    }
+    drop(stream);
+
+    // The walk may only have run ahead of the stream by its buffer
+    assert!(settled(&filter).await < 1000);
+}
+
+#[tokio::test]
+async fn dropping_the_future_stops_the_walk() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path().join("root");
+    create_many_files(&root, 5000);
+
+    let filter = CountingFilter {
+        delay: Some(Duration::from_millis(1)),
+        ..Default::default()
+    };
+    let collect = async_walk::collect(&root, filter.clone(), WalkOptions::default());
+    let timeout = tokio::time::timeout(Duration::from_millis(100), collect).await;
+    assert!(timeout.is_err());
+
+    assert!(settled(&filter).await < 5000);
+}
+
+#[tokio::test]
+async fn cancel_token_from_the_options_still_applies() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &small_tree()).unwrap();
+    let token = CancelToken::new();
+    token.cancel();
+    let options = WalkOptions {
+        cancel: Some(token.clone()),
+        ..Default::default()
+    };
+
+    let report =
+        async_walk::collect(tmp.path().join("root"), CountingFilter::default(), options).await;
+    assert_eq!(report.partial, Some(PartialReason::Cancelled));
+
+    // Dropping a walk doesn't cancel the token it was given
+    let token = CancelToken::new();
+    let options = WalkOptions {
+        cancel: Some(token.clone()),
+        ..Default::default()
+    };
+    drop(async_walk::walk(
+        tmp.path().join("root"),
+        CountingFilter::default(),
+        options,
+    ));
+    assert!(!token.is_cancelled());
+}
@@ -1,4 +1,9 @@
-use fsx::{collect, filter::GitIgnoreFilter, test_utils::{FsNode, create_fs_tree}};
+use fsx::{
+    ErrorPolicy, FindType, FindVisitor, FsErrorKind, PartialReason, PathFilter, WalkOptions,
+    WalkStrategy, collect, collect_roots, collect_roots_with, collect_with_options,
+    filter::GitIgnoreFilter,
+    test_utils::{FsNode, create_fs_tree},
+};
 use tempfile::tempdir;
 
 #[test]
@@ -78,7 +83,96 @@ fn collects_and_reports_stats_with_max_depth() {
     assert_eq!(stats.max_depth, 2);
 }
 
-#[cfg(target_os = "windows")]
+#[test]
+fn collects_same_stats_in_parallel() {
+    // Setup temp dir
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"), // 5 bytes
+            FsNode::Dir(
+                "subdir",
+                vec![
+                    FsNode::File("file2.txt", "world!"), // 6 bytes
+                    FsNode::Dir(
+                        "subdir2",
+                        vec![
+                            FsNode::File("file3.txt", "hello world!"), // 12 bytes
+                        ],
+                    ),
+                ],
+            ),
+            FsNode::Dir("other", vec![FsNode::File("file4.txt", "!")]), // 1 byte
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let root = tmp_path.join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+
+    let report = collect_with_options(&root, &filter, &options);
+    let stats = report.stats;
+
+    assert!(report.errors.is_empty());
+    assert_eq!(stats.total_files, 4);
+    assert_eq!(stats.total_dirs, 3);
+    assert_eq!(stats.total_size, 24);
+    assert_eq!(
+        stats.largest_file,
+        Some((root.join("subdir/subdir2/file3.txt"), 12))
+    );
+    assert_eq!(stats.max_depth, 3);
+}
+
+#[test]
+fn collects_same_stats_breadth_first() {
+    // Setup temp dir
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"), // 5 bytes
+            FsNode::Dir(
+                "subdir",
+                vec![
+                    FsNode::File("file2.txt", "world!"), // 6 bytes
+                    FsNode::Dir(
+                        "subdir2",
+                        vec![
+                            FsNode::File("file3.txt", "hello world!"), // 12 bytes
+                        ],
+                    ),
+                ],
+            ),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let root = tmp_path.join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+    let options = WalkOptions {
+        strategy: WalkStrategy::BreadthFirst,
+        ..Default::default()
+    };
+
+    let stats = collect_with_options(&root, &filter, &options).stats;
+
+    assert_eq!(stats.total_files, 3);
+    assert_eq!(stats.total_dirs, 2);
+    assert_eq!(stats.total_size, 23);
+    assert_eq!(
+        stats.largest_file,
+        Some((root.join("subdir/subdir2/file3.txt"), 12))
+    );
+    assert_eq!(stats.max_depth, 3);
+}
+
+#[cfg(any(target_os = "windows", unix))]
 #[test]
 fn collects_and_reports_stats_with_symlinks() {
     // Setup temp dir
@@ -115,3 +209,456 @@ fn collects_and_reports_stats_with_symlinks() {
     assert_eq!(stats.largest_file, Some((root.join("subdir/file2.txt"), 6)));
     assert_eq!(stats.max_depth, 2);
 }
+
+#[cfg(unix)]
+#[test]
+fn collects_special_files_per_kind() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"),
+            FsNode::Fifo("pipe"),
+            FsNode::Dir("run", vec![FsNode::Socket("service.sock")]),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let root = tmp_path.join("root");
+    std::os::unix::fs::symlink("/dev/null", root.join("null")).unwrap();
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+
+    let stats = collect(&root, None, false, &filter).stats;
+    assert_eq!(stats.total_files, 1);
+    assert_eq!(stats.total_fifos, 1);
+    assert_eq!(stats.total_sockets, 1);
+    assert_eq!(stats.total_char_devices, 0);
+    assert_eq!(stats.total_symlinks, 1);
+    assert_eq!(stats.max_depth, 2);
+
+    // Following the link reaches the device node
+    let stats = collect(&root, None, true, &filter).stats;
+    assert_eq!(stats.total_char_devices, 1);
+    assert_eq!(stats.total_block_devices, 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn counts_dangling_symlinks() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"),
+            FsNode::SymlinkFile("link", "file.txt"),
+            FsNode::SymlinkFile("dangling", "missing.txt"),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let root = tmp_path.join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+
+    for follow_symlinks in [false, true] {
+        let report = collect(&root, None, follow_symlinks, &filter);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.stats.total_symlinks, 2);
+        assert_eq!(report.stats.dangling_symlinks, 1);
+    }
+}
+
+#[test]
+fn min_depth_leaves_out_shallow_entries() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"),
+            FsNode::Dir(
+                "subdir",
+                vec![
+                    FsNode::File("file2.txt", "world!"),
+                    FsNode::Dir("subdir2", vec![FsNode::File("file3.txt", "hi")]),
+                ],
+            ),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let root = tmp_path.join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+    let options = WalkOptions {
+        min_depth: Some(2),
+        ..Default::default()
+    };
+
+    let stats = collect_with_options(&root, &filter, &options).stats;
+    assert_eq!(stats.total_files, 2);
+    assert_eq!(stats.total_dirs, 1);
+    assert_eq!(stats.total_size, 8);
+    assert_eq!(stats.max_depth, 3);
+
+    // Nothing is deep enough
+    let options = WalkOptions {
+        min_depth: Some(4),
+        ..Default::default()
+    };
+    let stats = collect_with_options(&root, &filter, &options).stats;
+    assert_eq!(stats.total_files, 0);
+    assert_eq!(stats.max_depth, 0);
+}
+
+#[test]
+fn collects_several_roots_without_double_counting() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "repos",
+        vec![
+            FsNode::Dir(
+                "one",
+                vec![
+                    FsNode::File(".gitignore", "*.log\n"),
+                    FsNode::File("a.txt", "a"),
+                    FsNode::File("a.log", "log"),
+                    FsNode::Dir("nested", vec![FsNode::File("b.txt", "bb")]),
+                ],
+            ),
+            FsNode::Dir(
+                "two",
+                vec![FsNode::File("c.txt", "ccc"), FsNode::File("c.log", "log")],
+            ),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let one = tmp_path.join("repos/one");
+    let nested = tmp_path.join("repos/one/nested");
+    let two = tmp_path.join("repos/two");
+    let filters: Vec<_> = [&one, &nested, &two, &one]
+        .iter()
+        .map(|root| GitIgnoreFilter::from_gitignore(root, &[]))
+        .collect();
+    let roots: Vec<(&std::path::Path, &dyn PathFilter)> = [&one, &nested, &two, &one]
+        .iter()
+        .zip(&filters)
+        .map(|(root, filter)| (root.as_path(), filter as &dyn PathFilter))
+        .collect();
+
+    let report = collect_roots(&roots, &WalkOptions::default());
+
+    // Every root is reported in full, with its own .gitignore
+    let files: Vec<_> = report
+        .roots
+        .iter()
+        .map(|(_, root_report)| root_report.stats.total_files)
+        .collect();
+    assert_eq!(files, vec![3, 1, 2, 3]);
+    assert_eq!(report.roots[0].0, one);
+
+    // The nested root and the repeated root are only counted once in the total
+    assert_eq!(report.total.total_files, 5);
+    assert_eq!(report.total.total_dirs, 1);
+    assert_eq!(report.total.total_size, 1 + 2 + 3 + 3 + 6);
+    assert_eq!(report.total.largest_file, Some((one.join(".gitignore"), 6)));
+}
+
+#[test]
+fn nested_roots_the_outer_root_ignores_are_counted_on_their_own() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "one",
+        vec![
+            FsNode::File(".gitignore", "nested/\n"),
+            FsNode::File("a.txt", "a"),
+            FsNode::Dir(
+                "nested",
+                vec![
+                    FsNode::File("b.txt", "bb"),
+                    FsNode::Dir("deeper", vec![FsNode::File("c.txt", "ccc")]),
+                ],
+            ),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let one = tmp_path.join("one");
+    let nested = one.join("nested");
+    let deeper = nested.join("deeper");
+    let filters: Vec<_> = [&one, &nested, &deeper]
+        .iter()
+        .map(|root| GitIgnoreFilter::from_gitignore(root, &[]))
+        .collect();
+    let roots: Vec<(&std::path::Path, &dyn PathFilter)> = [&one, &nested, &deeper]
+        .iter()
+        .zip(&filters)
+        .map(|(root, filter)| (root.as_path(), filter as &dyn PathFilter))
+        .collect();
+
+    let finder = FindVisitor::new(r"\.txt$", FindType::File).unwrap();
+    let (report, finder) = collect_roots_with(&roots, &WalkOptions::default(), finder);
+
+    let files: Vec<_> = report
+        .roots
+        .iter()
+        .map(|(_, root_report)| root_report.stats.total_files)
+        .collect();
+    assert_eq!(files, vec![2, 2, 1]);
+    // `nested` is walked on its own and covers `deeper`
+    assert_eq!(report.total.total_files, 4);
+    assert_eq!(report.total.total_size, 8 + 1 + 2 + 3);
+    let mut found: Vec<_> = finder
+        .into_report(None)
+        .entries
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    found.sort();
+    assert_eq!(
+        found,
+        vec![
+            one.join("a.txt"),
+            nested.join("b.txt"),
+            deeper.join("c.txt")
+        ]
+    );
+}
+
+#[test]
+fn nested_roots_below_the_max_depth_of_the_outer_root_are_counted_on_their_own() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "outer",
+        vec![
+            FsNode::File("a.txt", "a"),
+            FsNode::Dir("inner", vec![FsNode::File("b.txt", "bb")]),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let outer = tmp_path.join("outer");
+    let inner = outer.join("inner");
+    let filters: Vec<_> = [&outer, &inner]
+        .iter()
+        .map(|root| GitIgnoreFilter::from_gitignore(root, &[]))
+        .collect();
+    let roots: Vec<(&std::path::Path, &dyn PathFilter)> = [&outer, &inner]
+        .iter()
+        .zip(&filters)
+        .map(|(root, filter)| (root.as_path(), filter as &dyn PathFilter))
+        .collect();
+    let options = WalkOptions {
+        max_depth: Some(1),
+        ..Default::default()
+    };
+
+    let report = collect_roots(&roots, &options);
+
+    // The walk of `outer` enters `inner` but doesn't go inside
+    assert_eq!(report.roots[0].1.stats.total_files, 1);
+    assert_eq!(report.roots[1].1.stats.total_files, 1);
+    assert_eq!(report.total.total_files, 2);
+    assert_eq!(report.total.total_dirs, 1);
+    assert_eq!(report.total.total_size, 3);
+}
+
+#[test]
+fn visitor_walked_along_several_roots_sees_every_entry_once() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "repos",
+        vec![
+            FsNode::Dir(
+                "one",
+                vec![
+                    FsNode::File("a.txt", "a"),
+                    FsNode::Dir("nested", vec![FsNode::File("b.txt", "bb")]),
+                ],
+            ),
+            FsNode::Dir("two", vec![FsNode::File("c.txt", "ccc")]),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let one = tmp_path.join("repos/one");
+    let nested = tmp_path.join("repos/one/nested");
+    let two = tmp_path.join("repos/two");
+    let filters: Vec<_> = [&one, &nested, &two]
+        .iter()
+        .map(|root| GitIgnoreFilter::from_gitignore(root, &[]))
+        .collect();
+    let roots: Vec<(&std::path::Path, &dyn PathFilter)> = [&one, &nested, &two]
+        .iter()
+        .zip(&filters)
+        .map(|(root, filter)| (root.as_path(), filter as &dyn PathFilter))
+        .collect();
+
+    let finder = FindVisitor::new(r"\.txt$", FindType::File).unwrap();
+    let (report, finder) = collect_roots_with(&roots, &WalkOptions::default(), finder);
+
+    assert_eq!(report.total.total_files, 3);
+    let mut found: Vec<_> = finder
+        .into_report(None)
+        .entries
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    found.sort();
+    assert_eq!(
+        found,
+        vec![one.join("a.txt"), nested.join("b.txt"), two.join("c.txt")]
+    );
+}
+
+#[test]
+fn reports_are_marked_partial_when_a_limit_is_hit() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("a.txt", "a"),
+            FsNode::File("b.txt", "b"),
+            FsNode::File("c.txt", "c"),
+        ],
+    );
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let root = tmp_path.join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+
+    let options = WalkOptions {
+        max_entries: Some(2),
+        ..Default::default()
+    };
+    let report = collect_with_options(&root, &filter, &options);
+    assert_eq!(report.partial, Some(PartialReason::MaxEntries));
+    assert_eq!(report.stats.total_files, 2);
+
+    let report = collect_with_options(&root, &filter, &WalkOptions::default());
+    assert_eq!(report.partial, None);
+    assert_eq!(report.stats.total_files, 3);
+}
+
+// Symlinks pointing at themselves can't be followed, giving one error each
+#[cfg(unix)]
+fn tree_with_symlink_loops() -> FsNode<'static> {
+    FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("a.txt", "a"),
+            FsNode::SymlinkFile("loop1", "loop1"),
+            FsNode::SymlinkFile("loop2", "loop2"),
+            FsNode::SymlinkFile("loop3", "loop3"),
+        ],
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn error_policy_decides_which_errors_are_reported() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &tree_with_symlink_loops()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+
+    for threads in [1, 4] {
+        let report_with = |error_policy| {
+            let options = WalkOptions {
+                follow_symlinks: true,
+                error_policy,
+                threads,
+                ..Default::default()
+            };
+            collect_with_options(&root, &filter, &options)
+        };
+
+        let report = report_with(ErrorPolicy::Collect);
+        assert_eq!(report.errors.len(), 3);
+        assert_eq!(report.partial, None);
+        assert_eq!(report.stats.total_files, 1);
+
+        let report = report_with(ErrorPolicy::Ignore);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.partial, None);
+        assert_eq!(report.stats.total_files, 1);
+
+        let report = report_with(ErrorPolicy::FailFast);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.partial, Some(PartialReason::Error));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn errors_are_grouped_by_kind() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &tree_with_symlink_loops()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = GitIgnoreFilter::new(&root, &Vec::new());
+    let options = WalkOptions {
+        follow_symlinks: true,
+        ..Default::default()
+    };
+
+    let report = collect_with_options(&root, &filter, &options);
+    let summary = report.error_summary();
+    let kind = report.errors[0].kind();
+    assert!(matches!(kind, FsErrorKind::Io(_)));
+    assert_eq!(summary.total(), 3);
+    assert_eq!(summary.count(kind), 3);
+    assert_eq!(summary.count(FsErrorKind::Regex), 0);
+    assert_eq!(summary.to_string(), format!("3 {}", kind));
+}
+
+#[test]
+fn nested_gitignore_files_apply_in_every_walk_strategy() {
+    let tmp = tempdir().unwrap();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File(".gitignore", "*.log\n"),
+            FsNode::File("a.log", "a"),
+            FsNode::Dir(
+                "app",
+                vec![
+                    FsNode::File(".gitignore", "/target/\n!keep.log\n"),
+                    FsNode::File("keep.log", "k"),
+                    FsNode::File("main.rs", "m"),
+                    FsNode::Dir("target", vec![FsNode::File("out.bin", "o")]),
+                    FsNode::Dir(
+                        "lib",
+                        vec![
+                            FsNode::File("lib.rs", "l"),
+                            FsNode::Dir("target", vec![FsNode::File("kept.bin", "k")]),
+                        ],
+                    ),
+                ],
+            ),
+        ],
+    );
+    create_fs_tree(tmp.path(), &tree).unwrap();
+    let root = tmp.path().join("root");
+    let filter = GitIgnoreFilter::from_gitignore(&root, &[]);
+
+    for (threads, strategy) in [
+        (1, WalkStrategy::DepthFirst),
+        (1, WalkStrategy::BreadthFirst),
+        (4, WalkStrategy::DepthFirst),
+    ] {
+        let options = WalkOptions {
+            threads,
+            strategy,
+            ..Default::default()
+        };
+        let report = collect_with_options(&root, &filter, &options);
+
+        // .gitignore, app/.gitignore, keep.log, main.rs, lib.rs and kept.bin
+        assert_eq!(
+            report.stats.total_files, 6,
+            "{threads} threads, {strategy:?}"
+        );
+        assert_eq!(
+            report.stats.total_dirs, 3,
+            "{threads} threads, {strategy:?}"
+        );
+    }
+}
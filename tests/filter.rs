@@ -280,4 +280,780 @@ mod gitignore_tests {
         assert!(filter.is_ignored(&p("b.txt"), false));
         assert!(filter.is_ignored(&p("sub/b.txt"), false));
     }
+
+    #[test]
+    fn nested_gitignore_applies_below_its_directory() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        write_gitignore(root, "*.log\n*.tmp\n");
+        write_gitignore(&root.join("sub"), "!keep.log\n/build/\n*.out\n");
+
+        let filter = GitIgnoreFilter::from_gitignore(root, &[]);
+        let is_ignored = |rel: &str, is_dir| filter.is_ignored(&root.join(rel), is_dir);
+
+        // Deeper files take precedence
+        assert!(is_ignored("keep.log", false));
+        assert!(!is_ignored("sub/keep.log", false));
+        assert!(!is_ignored("sub/deeper/keep.log", false));
+        assert!(is_ignored("sub/other.log", false));
+        assert!(is_ignored("sub/x.tmp", false));
+
+        // Patterns are relative to the directory of their file
+        assert!(is_ignored("sub/build", true));
+        assert!(is_ignored("sub/build/a.txt", false));
+        assert!(!is_ignored("build", true));
+        assert!(!is_ignored("sub/deeper/build", true));
+        assert!(is_ignored("sub/deeper/x.out", false));
+        assert!(!is_ignored("x.out", false));
+    }
+
+    #[test]
+    fn cli_patterns_take_precedence_over_nested_gitignore() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        write_gitignore(&root.join("sub"), "*.txt\n");
+
+        let filter = GitIgnoreFilter::from_gitignore(root, &["!keep.txt".into()]);
+        assert!(!filter.is_ignored(&root.join("sub/keep.txt"), false));
+        assert!(filter.is_ignored(&root.join("sub/other.txt"), false));
+    }
+
+    #[test]
+    fn nested_gitignore_is_dropped_on_exit_dir() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let sub = root.join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        write_gitignore(&sub, "*.txt\n");
+
+        let filter = GitIgnoreFilter::from_gitignore(root, &[]);
+        filter.enter_dir(&sub);
+        std::fs::remove_file(sub.join(".gitignore")).unwrap();
+        assert!(filter.is_ignored(&sub.join("a.txt"), false));
+
+        // Asking again after exiting reads the directory again
+        filter.exit_dir(&sub);
+        assert!(!filter.is_ignored(&sub.join("a.txt"), false));
+    }
+
+    #[test]
+    fn nested_gitignores_are_all_dropped_after_a_walk() {
+        use fsx::{WalkOptions, WalkStrategy, collect_with_options};
+
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let mut dirs = Vec::new();
+        for top in 0..8 {
+            let top = root.join(format!("d{top}"));
+            for sub in 0..3 {
+                let dir = top.join(format!("s{sub}"));
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(dir.join("a.txt"), "a").unwrap();
+                dirs.push(dir);
+            }
+            dirs.push(top);
+        }
+
+        for (threads, strategy) in [
+            (4, WalkStrategy::DepthFirst),
+            (4, WalkStrategy::BreadthFirst),
+            (1, WalkStrategy::BreadthFirst),
+        ] {
+            for dir in &dirs {
+                write_gitignore(dir, "*.txt\n");
+            }
+            let filter = GitIgnoreFilter::from_gitignore(root, &[]);
+            let options = WalkOptions {
+                threads,
+                strategy,
+                ..Default::default()
+            };
+            let report = collect_with_options(root, &filter, &options);
+            // Only the .gitignore files themselves are left
+            assert_eq!(report.stats.total_files, dirs.len());
+
+            // Nothing is kept once the walk is over, so every directory is read
+            // again and found without its .gitignore
+            for dir in &dirs {
+                let _ = std::fs::remove_file(dir.join(".gitignore"));
+            }
+            for dir in &dirs {
+                assert!(
+                    !filter.is_ignored(&dir.join("a.txt"), false),
+                    "{} was kept with {threads} threads, {strategy:?}",
+                    dir.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn filters_from_patterns_ignore_nested_gitignore() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        write_gitignore(&root.join("sub"), "*.txt\n");
+
+        let filter = GitIgnoreFilter::new(root, &[]);
+        assert!(!filter.is_ignored(&root.join("sub/a.txt"), false));
+    }
+}
+
+mod git_excludes_tests {
+    use fsx::filter::{GitIgnoreFilter, IgnoreOptions, PathFilter};
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn no_global() -> IgnoreOptions {
+        IgnoreOptions {
+            global_ignore: false,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    // The file names `fsx find` lists under `root`, run with `home` as the home
+    // directory
+    pub(super) fn find_names(
+        root: &Path,
+        home: &Path,
+        xdg_config_home: Option<&Path>,
+        args: &[&str],
+    ) -> Vec<String> {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fsx"));
+        command.arg("find").arg(root).args(args).env("HOME", home);
+        match xdg_config_home {
+            Some(dir) => command.env("XDG_CONFIG_HOME", dir),
+            None => command.env_remove("XDG_CONFIG_HOME"),
+        };
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let mut names: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .split_whitespace()
+            .filter_map(|word| Some(Path::new(word).file_name()?.to_str()?.to_string()))
+            .filter(|name| name.contains('.'))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn info_exclude_applies_below_gitignore() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        write(&repo.join(".git/info/exclude"), "*.secret\n/build/\n");
+        write(&repo.join(".gitignore"), "!keep.secret\n");
+
+        let filter = GitIgnoreFilter::with_options(repo, &[], &no_global());
+        assert!(filter.is_ignored(&repo.join("a.secret"), false));
+        assert!(filter.is_ignored(&repo.join("sub/a.secret"), false));
+        assert!(filter.is_ignored(&repo.join("build"), true));
+        assert!(!filter.is_ignored(&repo.join("sub/build"), true));
+
+        // Any .gitignore takes precedence
+        assert!(!filter.is_ignored(&repo.join("keep.secret"), false));
+    }
+
+    #[test]
+    fn info_exclude_is_relative_to_the_top_of_the_repository() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        write(&repo.join(".git/info/exclude"), "/sub/build/\n");
+        let sub = repo.join("sub");
+        fs::create_dir(&sub).unwrap();
+
+        let filter = GitIgnoreFilter::with_options(&sub, &[], &no_global());
+        assert!(filter.is_ignored(&sub.join("build"), true));
+        assert!(!filter.is_ignored(&sub.join("other/build"), true));
+    }
+
+    #[test]
+    fn info_exclude_of_a_worktree_is_the_one_of_the_main_repository() {
+        let dir = tempdir().unwrap();
+        let main = dir.path().join("main");
+        write(&main.join(".git/info/exclude"), "*.secret\n");
+        write(&main.join(".git/worktrees/wt/commondir"), "../..\n");
+        let worktree = dir.path().join("wt");
+        write(
+            &worktree.join(".git"),
+            &format!("gitdir: {}\n", main.join(".git/worktrees/wt").display()),
+        );
+
+        let filter = GitIgnoreFilter::with_options(&worktree, &[], &no_global());
+        assert!(filter.is_ignored(&worktree.join("a.secret"), false));
+    }
+
+    #[test]
+    fn global_excludes_file_comes_from_the_git_config() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().join("home");
+        write(
+            &home.join(".gitconfig"),
+            "[user]\n\tname = Someone\n[core]\n\texcludesFile = \"~/my ignore\" # comment\n",
+        );
+        write(&home.join("my ignore"), "*.bak\n");
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        write(&root.join("a.txt"), "a");
+        write(&root.join("a.bak"), "b");
+
+        assert_eq!(find_names(&root, &home, None, &[]), vec!["a.txt"]);
+        assert_eq!(
+            find_names(&root, &home, None, &["--no-global-ignore"]),
+            vec!["a.bak", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn global_excludes_file_defaults_to_the_xdg_config_home() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().join("home");
+        let config_home = dir.path().join("config");
+        write(&config_home.join("git/ignore"), "*.bak\n");
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        write(&root.join("a.txt"), "a");
+        write(&root.join("a.bak"), "b");
+
+        assert_eq!(
+            find_names(&root, &home, Some(&config_home), &[]),
+            vec!["a.txt"]
+        );
+
+        // Without XDG_CONFIG_HOME it is looked for in ~/.config
+        write(&home.join(".config/git/ignore"), "*.txt\n");
+        assert_eq!(find_names(&root, &home, None, &[]), vec!["a.bak"]);
+    }
+
+    #[test]
+    fn global_excludes_file_only_applies_in_a_repository() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().join("home");
+        let config_home = dir.path().join("config");
+        write(&config_home.join("git/ignore"), "*.bak\n");
+        let root = dir.path().join("root");
+        write(&root.join("a.txt"), "a");
+        write(&root.join("a.bak"), "b");
+
+        assert_eq!(
+            find_names(&root, &home, Some(&config_home), &[]),
+            vec!["a.bak", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn global_excludes_file_can_be_the_home_directory_itself() {
+        // `~` on its own is the home directory, which is a file here
+        let dir = tempdir().unwrap();
+        let home = dir.path().join("home");
+        write(&home, "*.bak\n");
+        let config_home = dir.path().join("config");
+        write(
+            &config_home.join("git/config"),
+            "[core]\n\texcludesFile = ~\n",
+        );
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        write(&root.join("a.txt"), "a");
+        write(&root.join("a.bak"), "b");
+
+        assert_eq!(
+            find_names(&root, &home, Some(&config_home), &[]),
+            vec!["a.txt"]
+        );
+    }
+}
+
+mod ignore_files_tests {
+    use super::git_excludes_tests::{find_names, write};
+    use fsx::filter::{GitIgnoreFilter, IgnoreOptions, PathFilter};
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn options() -> IgnoreOptions {
+        IgnoreOptions {
+            global_ignore: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fsxignore_and_ignore_take_precedence_over_gitignore() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join(".gitignore"), "*.log\n*.tmp\n");
+        write(&root.join(".ignore"), "!keep.log\n!*.tmp\n");
+        write(&root.join(".fsxignore"), "drop.tmp\n");
+        write(&root.join("sub/.gitignore"), "!sub.log\n");
+
+        let filter = GitIgnoreFilter::with_options(root, &[], &options());
+        assert!(filter.is_ignored(&root.join("a.log"), false));
+        assert!(!filter.is_ignored(&root.join("keep.log"), false));
+        assert!(!filter.is_ignored(&root.join("a.tmp"), false));
+        assert!(filter.is_ignored(&root.join("drop.tmp"), false));
+
+        // A deeper .gitignore still beats the ignore files above it
+        assert!(!filter.is_ignored(&root.join("sub/sub.log"), false));
+        assert!(filter.is_ignored(&root.join("sub/a.log"), false));
+    }
+
+    #[test]
+    fn nested_ignore_files_apply_to_their_directory() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join("sub/.ignore"), "*.txt\n");
+        write(&root.join("sub/deeper/.fsxignore"), "!keep.txt\n");
+
+        let filter = GitIgnoreFilter::with_options(root, &[], &options());
+        assert!(!filter.is_ignored(&root.join("a.txt"), false));
+        assert!(filter.is_ignored(&root.join("sub/a.txt"), false));
+        assert!(filter.is_ignored(&root.join("sub/deeper/a.txt"), false));
+        assert!(!filter.is_ignored(&root.join("sub/deeper/keep.txt"), false));
+    }
+
+    #[test]
+    fn no_vcs_ignore_and_no_ignore_turn_off_the_automatic_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join(".git/info/exclude"), "*.secret\n");
+        write(&root.join(".gitignore"), "*.log\n");
+        write(&root.join("sub/.gitignore"), "*.txt\n");
+        write(&root.join(".ignore"), "*.tmp\n");
+
+        let no_vcs = IgnoreOptions {
+            vcs_ignore: false,
+            ..options()
+        };
+        let filter = GitIgnoreFilter::with_options(root, &[], &no_vcs);
+        assert!(!filter.is_ignored(&root.join("a.log"), false));
+        assert!(!filter.is_ignored(&root.join("sub/a.txt"), false));
+        assert!(!filter.is_ignored(&root.join("a.secret"), false));
+        assert!(filter.is_ignored(&root.join("a.tmp"), false));
+
+        let none = IgnoreOptions {
+            automatic: false,
+            ..options()
+        };
+        let filter = GitIgnoreFilter::with_options(root, &["*.md".to_string()], &none);
+        assert!(!filter.is_ignored(&root.join("a.log"), false));
+        assert!(!filter.is_ignored(&root.join("a.secret"), false));
+        assert!(!filter.is_ignored(&root.join("a.tmp"), false));
+        assert!(filter.is_ignored(&root.join("a.md"), false));
+    }
+
+    #[test]
+    fn ignore_files_rank_below_the_ignore_files_in_the_tree() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let patterns = dir.path().join("patterns");
+        write(&patterns, "*.bak\n/build/\n");
+        write(&root.join(".gitignore"), "!keep.bak\n");
+
+        let with_file = IgnoreOptions {
+            ignore_files: vec![patterns],
+            ..options()
+        };
+        let filter = GitIgnoreFilter::with_options(&root, &[], &with_file);
+        assert!(filter.is_ignored(&root.join("a.bak"), false));
+        assert!(filter.is_ignored(&root.join("sub/a.bak"), false));
+        assert!(!filter.is_ignored(&root.join("keep.bak"), false));
+        assert!(filter.is_ignored(&root.join("build"), true));
+        assert!(!filter.is_ignored(&root.join("sub/build"), true));
+    }
+
+    #[test]
+    fn exclude_from_lists_plain_paths() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let list = dir.path().join("list");
+        write(
+            &list,
+            &format!(
+                "./build\nsrc/[gen].rs\n\n{}\n/elsewhere/x\n",
+                root.join("abs.txt").display()
+            ),
+        );
+        write(&root.join(".gitignore"), "!*\n");
+
+        let with_list = IgnoreOptions {
+            exclude_from: vec![list],
+            ..options()
+        };
+        let filter = GitIgnoreFilter::with_options(&root, &[], &with_list);
+        assert!(filter.is_ignored(&root.join("build"), true));
+        assert!(filter.is_ignored(&root.join("build/out/a.o"), false));
+        assert!(filter.is_ignored(&root.join("src/[gen].rs"), false));
+        assert!(!filter.is_ignored(&root.join("src/g.rs"), false));
+        assert!(!filter.is_ignored(&root.join("sub/build"), true));
+        assert!(filter.is_ignored(&root.join("abs.txt"), false));
+        assert!(!filter.is_ignored(&root.join("x"), false));
+    }
+
+    #[test]
+    fn ignore_sources_can_be_chosen_from_the_command_line() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().join("home");
+        let root = dir.path().join("root");
+        write(&root.join(".gitignore"), "*.log\n");
+        write(&root.join(".fsxignore"), "*.tmp\n");
+        for name in ["a.txt", "a.log", "a.tmp", "a.bak", "a.md"] {
+            write(&root.join(name), "x");
+        }
+        // The ignore files themselves are listed too
+        let listed = |root, home, xdg, args| -> Vec<String> {
+            let mut names = find_names(root, home, xdg, args);
+            names.retain(|name| !name.starts_with('.'));
+            names
+        };
+        let patterns = dir.path().join("patterns");
+        write(&patterns, "*.bak\n");
+        let list = dir.path().join("list");
+        write(&list, "a.md\n");
+
+        assert_eq!(
+            listed(&root, &home, None, &[]),
+            vec!["a.bak", "a.md", "a.txt"]
+        );
+        assert_eq!(
+            listed(&root, &home, None, &["--no-vcs-ignore"]),
+            vec!["a.bak", "a.log", "a.md", "a.txt"]
+        );
+        assert_eq!(
+            listed(&root, &home, None, &["--no-ignore"]),
+            vec!["a.bak", "a.log", "a.md", "a.tmp", "a.txt"]
+        );
+        let ignore_file = patterns.to_str().unwrap();
+        let exclude_from = list.to_str().unwrap();
+        assert_eq!(
+            listed(
+                &root,
+                &home,
+                None,
+                &["--ignore-file", ignore_file, "--exclude-from", exclude_from],
+            ),
+            vec!["a.txt"]
+        );
+    }
+
+    #[test]
+    fn explicit_files_are_found_relative_to_the_working_directory() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let elsewhere = dir.path().join("elsewhere");
+        for name in ["a.txt", "a.bak", "a.md", "b.md"] {
+            write(&root.join(name), "x");
+        }
+        write(&root.join("patterns"), "*.bak\npatterns\nlist\n");
+        // Absolute paths in the list use the canonical root while the root is given
+        // relative to the working directory
+        let canonical = std::fs::canonicalize(&root).unwrap();
+        write(
+            &root.join("list"),
+            &format!("{}\nb.md\n", canonical.join("a.md").display()),
+        );
+        std::fs::create_dir(&elsewhere).unwrap();
+
+        let fsx = |args: &[&str]| {
+            Command::new(env!("CARGO_BIN_EXE_fsx"))
+                .current_dir(&elsewhere)
+                .arg("find")
+                .arg("../root")
+                .args(["--no-global-ignore"])
+                .args(args)
+                .output()
+                .unwrap()
+        };
+
+        // Like PATH, the files are relative to the working directory, while the
+        // patterns and paths in them are relative to PATH
+        let output = fsx(&[
+            "--ignore-file",
+            "../root/patterns",
+            "--exclude-from",
+            "../root/list",
+        ]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut names: Vec<&str> = stdout
+            .split_whitespace()
+            .filter_map(|word| Path::new(word).file_name()?.to_str())
+            .filter(|name| name.contains('.'))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt"]);
+
+        // A file that was asked for and isn't there is an error, even if PATH has one
+        // by that name
+        for flag in ["--ignore-file", "--exclude-from"] {
+            let output = fsx(&[flag, "missing"]);
+            assert!(!output.status.success());
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains("missing"), "{stderr}");
+            let output = fsx(&[flag, "patterns"]);
+            assert!(!output.status.success());
+        }
+    }
+}
+
+mod pattern_error_tests {
+    use super::git_excludes_tests::write;
+    use fsx::filter::{GitIgnoreFilter, IgnoreOptions, PathFilter};
+    use fsx::{FsError, FsErrorKind, PatternError};
+    use std::io;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn options() -> IgnoreOptions {
+        IgnoreOptions {
+            global_ignore: false,
+            ..Default::default()
+        }
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn invalid_cli_pattern_fails_the_strict_constructor() {
+        let root = super::root();
+        let patterns = patterns(&["*.log", "a[b", "!keep.log"]);
+
+        let err = GitIgnoreFilter::try_new(&root, &patterns).err().unwrap();
+        let FsError::InvalidPattern(err) = err else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!(err.file, None);
+        assert_eq!(err.line, 2);
+        assert_eq!(err.pattern, "a[b");
+        assert!(!err.reason.is_empty());
+
+        // The lenient one leaves the pattern out and goes on
+        let filter = GitIgnoreFilter::new(&root, &patterns);
+        assert!(filter.is_ignored(&super::p("a.log"), false));
+        assert!(!filter.is_ignored(&super::p("keep.log"), false));
+        assert_eq!(filter.errors().len(), 1);
+        assert_eq!(filter.errors()[0].kind(), FsErrorKind::InvalidPattern);
+    }
+
+    #[test]
+    fn invalid_pattern_in_a_file_names_the_file_and_line() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join(".gitignore"), "# comment\n*.log\n\nsrc/[oops\n");
+
+        let err = GitIgnoreFilter::try_with_options(root, &[], &options())
+            .err()
+            .unwrap();
+        let FsError::InvalidPattern(PatternError {
+            file,
+            line,
+            pattern,
+            ..
+        }) = &err
+        else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!(file.as_deref(), Some(root.join(".gitignore").as_path()));
+        assert_eq!(*line, 4);
+        assert_eq!(pattern, "src/[oops");
+        assert!(
+            err.to_string()
+                .starts_with(&format!("{}:4: ", root.join(".gitignore").display()))
+        );
+
+        assert!(
+            GitIgnoreFilter::try_with_options(root, &patterns(&["*.tmp"]), &options()).is_err()
+        );
+        write(&root.join(".gitignore"), "*.log\n");
+        assert!(GitIgnoreFilter::try_with_options(root, &patterns(&["*.tmp"]), &options()).is_ok());
+    }
+
+    #[test]
+    fn missing_explicit_ignore_file_is_an_error() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let missing = root.join("missing");
+        let with_file = IgnoreOptions {
+            ignore_files: vec![missing.clone()],
+            ..options()
+        };
+
+        let err = GitIgnoreFilter::try_with_options(root, &[], &with_file)
+            .err()
+            .unwrap();
+        assert!(matches!(&err, FsError::IgnoreFile(path, _) if *path == missing));
+        assert_eq!(err.kind(), FsErrorKind::Io(io::ErrorKind::NotFound));
+
+        let with_list = IgnoreOptions {
+            exclude_from: vec![missing],
+            ..options()
+        };
+        assert!(GitIgnoreFilter::try_with_options(root, &[], &with_list).is_err());
+
+        // Files that are only looked for may be missing
+        assert!(GitIgnoreFilter::try_with_options(root, &[], &options()).is_ok());
+    }
+
+    #[test]
+    fn nested_ignore_file_problems_are_recorded_once() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join("sub/.ignore"), "*.txt\n[\n");
+
+        let filter = GitIgnoreFilter::try_with_options(root, &[], &options()).unwrap();
+        assert!(filter.errors().is_empty());
+        filter.enter_dir(&root.join("sub"));
+        assert!(filter.is_ignored(&root.join("sub/a.txt"), false));
+        assert!(filter.is_ignored(&root.join("sub/b.txt"), false));
+
+        let errors = filter.errors();
+        assert_eq!(errors.len(), 1);
+        let FsError::InvalidPattern(err) = &errors[0] else {
+            panic!("unexpected error {}", errors[0]);
+        };
+        assert_eq!(
+            err.file.as_deref(),
+            Some(root.join("sub/.ignore").as_path())
+        );
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn cli_reports_invalid_patterns_as_warnings() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(&root.join("a.txt"), "a");
+        write(&root.join("a.log"), "a");
+
+        let output = Command::new(env!("CARGO_BIN_EXE_fsx"))
+            .arg("find")
+            .arg(root)
+            .args(["-i", "*.log", "-i", "a[", "--no-global-ignore"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("a.txt") && !stdout.contains("a.log"));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr
+                .matches("Warning: invalid ignore pattern 'a['")
+                .count(),
+            1,
+            "{stderr}"
+        );
+    }
+}
+
+mod git_conformance_tests {
+    use super::git_excludes_tests::write;
+    use fsx::filter::{GitIgnoreFilter, IgnoreOptions, PathFilter};
+    use tempfile::tempdir;
+
+    // (.gitignore contents, path, is a directory, ignored). Whether a path is
+    // ignored is what `git check-ignore --no-index` says for the same `.gitignore`
+    // and path, with the global excludes file turned off.
+    const CASES: &[(&str, &str, bool, bool)] = &[
+        ("docs/build", "docs/build", true, true),
+        ("docs/build", "a/docs/build", true, false),
+        ("build", "a/b/build", false, true),
+        ("build", "build", true, true),
+        ("build/", "build", false, false),
+        ("build/", "build", true, true),
+        ("build/", "a/build", true, true),
+        ("docs/build/", "docs/build", true, true),
+        ("docs/build/", "a/docs/build", true, false),
+        ("/top", "top", false, true),
+        ("/top", "a/top", false, false),
+        ("*.log", "a/b.log", false, true),
+        ("doc/*.txt", "doc/a.txt", false, true),
+        ("doc/*.txt", "doc/sub/a.txt", false, false),
+        ("**/foo", "foo", false, true),
+        ("**/foo", "a/b/foo", false, true),
+        ("**/foo/bar", "foo/bar", false, true),
+        ("**/foo/bar", "a/foo/bar", false, true),
+        ("abc/**", "abc", true, false),
+        ("abc/**", "abc/x", false, true),
+        ("a/**/b", "a/b", false, true),
+        ("a/**/b", "a/x/y/b", false, true),
+        ("foo**bar", "foobar", false, true),
+        ("foo**bar", "fooxbar", false, true),
+        ("foo**bar", "foo/bar", false, false),
+        ("\\#hash", "#hash", false, true),
+        ("#comment", "#comment", false, false),
+        ("\\!bang", "!bang", false, true),
+        ("*.txt\n!keep.txt", "keep.txt", false, false),
+        ("*.txt\n!keep.txt", "a.txt", false, true),
+        ("trail  ", "trail", false, true),
+        ("trail  ", "trail ", false, false),
+        ("space\\ ", "space ", false, true),
+        ("space\\ ", "space", false, false),
+        ("two\\  ", "two ", false, true),
+        ("two\\  ", "two  ", false, false),
+        (" lead", " lead", false, true),
+        (" lead", "lead", false, false),
+        ("tab\t", "tab\t", false, true),
+        ("tab\t", "tab", false, false),
+        ("{a,b}", "{a,b}", false, true),
+        ("{a,b}", "a", false, false),
+        ("?.md", "a.md", false, true),
+        ("?.md", "ab.md", false, false),
+        ("[ab].c", "a.c", false, true),
+        ("[ab].c", "c.c", false, false),
+        ("[!ab].c", "c.c", false, true),
+        ("[!ab].c", "a.c", false, false),
+        ("a?b", "a/b", false, false),
+        ("a?b", "axb", false, true),
+        ("\\*star", "*star", false, true),
+        ("\\*star", "xstar", false, false),
+        ("/", "x", false, false),
+        ("*", "x", false, true),
+        ("*", "d", true, true),
+        ("a/b*", "a/bc", false, true),
+        ("a/b*", "x/a/bc", false, false),
+        ("**", "a/b", false, true),
+        ("x/", "x/y", true, true),
+    ];
+
+    #[test]
+    fn patterns_match_like_git_check_ignore() {
+        let options = IgnoreOptions {
+            global_ignore: false,
+            ..Default::default()
+        };
+        let mut failures = Vec::new();
+        for &(contents, path, is_dir, ignored) in CASES {
+            let dir = tempdir().unwrap();
+            let root = dir.path();
+            write(&root.join(".gitignore"), &format!("{contents}\n"));
+
+            let filter = GitIgnoreFilter::try_with_options(root, &[], &options).unwrap();
+            if filter.is_ignored(&root.join(path), is_dir) != ignored {
+                failures.push(format!(
+                    "{contents:?} {path:?}: expected ignored = {ignored}"
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
 }
@@ -1,7 +1,11 @@
 use fsx::test_utils::{FsNode, create_fs_tree, gitignore_filter};
-use fsx::{FindReport, find};
+use fsx::{
+    FindReport, FindType, FsErrorKind, SortBy, SpecialKind, WalkOptions, WalkStrategy, find,
+    find_by_type, find_roots, find_with_options,
+};
 use fsx::{GitIgnoreFilter, PathFilter};
 use std::path::Path;
+use std::process::Command;
 use tempfile::tempdir;
 
 fn run_find(root: &Path, filter: &dyn PathFilter, pattern: &str) -> FindReport {
@@ -124,4 +128,288 @@ fn invalid_regex_is_reported() {
 
     assert_eq!(report.entries.len(), 0);
     assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.error_summary().count(FsErrorKind::Regex), 1);
+}
+
+#[test]
+fn finds_files_in_parallel() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::Dir("target", vec![FsNode::File("a.txt", "a")]),
+                FsNode::Dir(
+                    "src",
+                    vec![FsNode::File("c.txt", "c"), FsNode::File("d.log", "d")],
+                ),
+                FsNode::File("b.txt", "b"),
+            ],
+        ),
+    )
+    .unwrap();
+
+    let filter = gitignore_filter(tmp.path(), &["target/"]);
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+    let report = find_with_options(tmp.path(), &filter, r".*\.txt", &options);
+
+    let mut names: Vec<_> = report.entries.iter().map(|e| e.name.clone()).collect();
+    names.sort();
+    assert_eq!(report.errors.len(), 0);
+    assert_eq!(
+        names,
+        vec![
+            tmp.path().join("root/b.txt"),
+            tmp.path().join("root/src/c.txt")
+        ]
+    );
+}
+
+#[test]
+fn breadth_first_find_lists_shallow_files_first() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::Dir(
+                    "a",
+                    vec![
+                        FsNode::Dir("deep", vec![FsNode::File("x.txt", "x")]),
+                        FsNode::File("y.txt", "y"),
+                    ],
+                ),
+                FsNode::File("z.txt", "z"),
+            ],
+        ),
+    )
+    .unwrap();
+
+    let filter = gitignore_filter(tmp.path(), &[]);
+    let options = WalkOptions {
+        strategy: WalkStrategy::BreadthFirst,
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+    let report = find_with_options(&tmp.path().join("root"), &filter, r".*\.txt", &options);
+
+    let depths: Vec<_> = report.entries.iter().map(|e| e.depth).collect();
+    assert_eq!(depths, vec![1, 2, 3]);
+    assert!(report.entries[0].name.ends_with("z.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn finds_special_files_by_type() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::File("a.sock.txt", "a"),
+                FsNode::Fifo("pipe"),
+                FsNode::Dir("run", vec![FsNode::Socket("service.sock")]),
+            ],
+        ),
+    )
+    .unwrap();
+
+    let filter = gitignore_filter(tmp.path(), &[]);
+    let options = WalkOptions::default();
+    let sockets = FindType::Special(SpecialKind::Socket);
+    let report = find_by_type(tmp.path(), &filter, r"\.sock$", sockets, &options);
+
+    assert_eq!(report.errors.len(), 0);
+    assert_eq!(report.entries.len(), 1);
+    assert!(report.entries[0].name.ends_with("run/service.sock"));
+
+    // Regular files are the default, special files are left out
+    let report = find_with_options(tmp.path(), &filter, r".*", &options);
+    assert_eq!(report.entries.len(), 1);
+    assert!(report.entries[0].name.ends_with("a.sock.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn finds_broken_symlinks() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::File("a.txt", "a"),
+                FsNode::SymlinkFile("good", "a.txt"),
+                FsNode::Dir("sub", vec![FsNode::SymlinkFile("bad", "missing.txt")]),
+            ],
+        ),
+    )
+    .unwrap();
+
+    let filter = gitignore_filter(tmp.path(), &[]);
+    let options = WalkOptions::default();
+    let report = find_by_type(
+        tmp.path(),
+        &filter,
+        r".*",
+        FindType::BrokenSymlink,
+        &options,
+    );
+
+    assert_eq!(report.errors.len(), 0);
+    assert_eq!(report.entries.len(), 1);
+    assert!(report.entries[0].name.ends_with("sub/bad"));
+}
+
+#[cfg(any(target_os = "windows", unix))]
+#[test]
+fn logical_paths_are_reported_and_filtered_below_symlinks() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "outside",
+            vec![FsNode::File("a.txt", "a"), FsNode::File("b.log", "b")],
+        ),
+    )
+    .unwrap();
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir("root", vec![FsNode::SymlinkDir("link", "../outside")]),
+    )
+    .unwrap();
+    let root = tmp.path().join("root");
+
+    let filter = gitignore_filter(&root, &["*.log"]);
+    let options = WalkOptions {
+        follow_symlinks: true,
+        logical_paths: true,
+        ..Default::default()
+    };
+    let report = find_with_options(&root, &filter, r".*", &options);
+
+    assert_eq!(report.errors.len(), 0);
+    assert_eq!(report.entries.len(), 1);
+    assert_eq!(report.entries[0].name, root.join("link/a.txt"));
+    assert_eq!(report.entries[0].depth, 2);
+}
+
+#[cfg(any(target_os = "windows", unix))]
+#[test]
+fn followed_symlinks_are_filtered_below_a_relative_root() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::Dir(
+                    "a",
+                    vec![FsNode::File("x.log", "x"), FsNode::File("y.txt", "y")],
+                ),
+                FsNode::SymlinkDir("link", "a"),
+            ],
+        ),
+    )
+    .unwrap();
+
+    // The entries below `link` come with their resolved, absolute path
+    let output = Command::new(env!("CARGO_BIN_EXE_fsx"))
+        .current_dir(tmp.path())
+        .args(["find", "root", "--follow-symlinks", "-i", "*.log"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("x.log"), "{stdout}");
+    assert!(stdout.contains("y.txt"), "{stdout}");
+}
+
+#[test]
+fn finds_files_in_several_roots_once() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::File("a.txt", "a"),
+                FsNode::Dir("sub", vec![FsNode::File("b.txt", "b")]),
+            ],
+        ),
+    )
+    .unwrap();
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir("other", vec![FsNode::File("c.txt", "c")]),
+    )
+    .unwrap();
+    let root = tmp.path().join("root");
+    let sub = root.join("sub");
+    let other = tmp.path().join("other");
+
+    let filter = gitignore_filter(tmp.path(), &[]);
+    let roots: Vec<(&Path, &dyn PathFilter)> =
+        vec![(&sub, &filter), (&root, &filter), (&other, &filter)];
+    let report = find_roots(&roots, r".*", FindType::File, &WalkOptions::default());
+
+    let mut names: Vec<_> = report.entries.iter().map(|e| e.name.clone()).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![other.join("c.txt"), root.join("a.txt"), sub.join("b.txt")]
+    );
+}
+
+#[test]
+fn roots_ignored_by_an_outer_root_are_searched_on_their_own() {
+    let tmp = tempdir().unwrap();
+
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::File("a.txt", "a"),
+                FsNode::Dir(
+                    "build",
+                    vec![
+                        FsNode::File("b.txt", "b"),
+                        FsNode::Dir("deep", vec![FsNode::File("c.txt", "c")]),
+                    ],
+                ),
+            ],
+        ),
+    )
+    .unwrap();
+    let root = tmp.path().join("root");
+    let build = root.join("build");
+    let deep = build.join("deep");
+
+    let outer = gitignore_filter(&root, &["build/"]);
+    let inner = gitignore_filter(&build, &[]);
+    let roots: Vec<(&Path, &dyn PathFilter)> =
+        vec![(&deep, &inner), (&root, &outer), (&build, &inner)];
+    let report = find_roots(&roots, r".*", FindType::File, &WalkOptions::default());
+
+    // `deep` is left to the walk of `build`, which the walk of `root` doesn't get to
+    let mut names: Vec<_> = report.entries.iter().map(|e| e.name.clone()).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![root.join("a.txt"), build.join("b.txt"), deep.join("c.txt")]
+    );
 }
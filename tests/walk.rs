@@ -1,11 +1,17 @@
-use fsx::error::FsError;
+use fsx::error::{FsError, PatternError};
+use fsx::walk::adapters::{Collector, Counter, FnVisitor, FsVisitorExt, Visit};
 use fsx::{
-    FsVisitor, PathFilter,
-    test_utils::{FsNode, create_fs_tree},
-    walk_dir,
+    CancelToken, EntryMeta, ErrorPolicy, FindType, FindVisitor, FsVisitor, MetadataNeeds,
+    ParallelFsVisitor, PartialReason, PathFilter, Progress, ProgressReporter, SortBy, StatsVisitor,
+    SymlinkInfo, WalkControl, WalkEvent, WalkOptions, WalkStrategy, Walker, collect_with_options,
+    find_with_options,
+    test_utils::{FsNode, create_fs_tree, stat_calls},
+    walk::natural_cmp,
+    walk_dir, walk_dir_parallel, walk_dir_with_options,
 };
-use std::fs::Metadata;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tempfile::tempdir;
 
 #[derive(Default, Debug)]
@@ -16,20 +22,20 @@ struct WalkTestVisitor {
 }
 
 impl FsVisitor for WalkTestVisitor {
-    fn visit_file(&mut self, path: &Path, _meta: &Metadata, depth: usize) {
+    fn visit_file(&mut self, path: &Path, _meta: &EntryMeta, depth: usize) -> WalkControl {
         self.seen_files.push((path.to_path_buf(), depth));
+        WalkControl::Continue
     }
 
-    fn enter_dir(&mut self, path: &Path, _meta: &Metadata, depth: usize) {
+    fn enter_dir(&mut self, path: &Path, _meta: &EntryMeta, depth: usize) -> WalkControl {
         self.seen_dirs_enter.push((path.to_path_buf(), depth));
+        WalkControl::Continue
     }
 
-    fn exit_dir(&mut self, path: &Path, _meta: &Metadata, depth: usize) {
+    fn exit_dir(&mut self, path: &Path, _meta: &EntryMeta, depth: usize) {
         self.seen_dirs_exit.push((path.to_path_buf(), depth));
     }
 
-    fn visit_symlink(&mut self, _path: &Path, _depth: usize) {}
-
     fn on_error(&mut self, error: FsError) {
         panic!("Unexpected error: {:?}", error);
     }
@@ -341,29 +347,36 @@ struct SymlinkTestVisitor {
     files: Vec<(PathBuf, usize)>,
     dirs: Vec<(PathBuf, usize)>,
     symlinks: Vec<(PathBuf, usize)>,
+    links: Vec<SymlinkInfo>,
+    cycles: Vec<(PathBuf, usize)>,
 }
 
 impl FsVisitor for SymlinkTestVisitor {
-    fn visit_file(&mut self, path: &Path, _meta: &Metadata, depth: usize) {
+    fn visit_file(&mut self, path: &Path, _meta: &EntryMeta, depth: usize) -> WalkControl {
         self.files.push((path.to_path_buf(), depth));
+        WalkControl::Continue
     }
 
-    fn enter_dir(&mut self, path: &Path, _meta: &Metadata, depth: usize) {
+    fn enter_dir(&mut self, path: &Path, _meta: &EntryMeta, depth: usize) -> WalkControl {
         self.dirs.push((path.to_path_buf(), depth));
+        WalkControl::Continue
     }
 
-    fn exit_dir(&mut self, _path: &Path, _meta: &Metadata, _depth: usize) {}
-
-    fn visit_symlink(&mut self, path: &Path, depth: usize) {
+    fn visit_symlink(&mut self, path: &Path, link: &SymlinkInfo, depth: usize) {
         self.symlinks.push((path.to_path_buf(), depth));
+        self.links.push(link.clone());
     }
 
     fn on_error(&mut self, err: FsError) {
         panic!("Unexpected error: {:?}", err);
     }
+
+    fn visit_cycle(&mut self, path: &Path, depth: usize) {
+        self.cycles.push((path.to_path_buf(), depth));
+    }
 }
 
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", unix))]
 #[test]
 fn detects_symlinks_but_does_not_follow_by_default() {
     let tmp = tempdir().unwrap();
@@ -417,7 +430,7 @@ fn detects_symlinks_but_does_not_follow_by_default() {
     );
 }
 
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", unix))]
 #[test]
 fn symlink_cycle_does_not_revisit_directories() {
     let tmp = tempdir().unwrap();
@@ -457,9 +470,124 @@ fn symlink_cycle_does_not_revisit_directories() {
         visitor.dirs.iter().any(|(p, _)| p.ends_with("a")),
         "Expected to reach directory 'a' via symlink"
     );
+
+    // `a/b` is the root and is reported as a cycle instead of being walked again
+    assert_eq!(visitor.cycles.len(), 1);
+    assert!(visitor.cycles[0].0.ends_with("a/b"));
+}
+
+#[cfg(any(target_os = "windows", unix))]
+#[test]
+fn directory_reached_through_several_links_is_walked_once() {
+    let tmp = tempdir().unwrap();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::Dir("shared", vec![FsNode::File("file.txt", "hello")]),
+            FsNode::SymlinkDir("link1", "shared"),
+            FsNode::SymlinkDir("link2", "shared"),
+        ],
+    );
+    create_fs_tree(tmp.path(), &tree).unwrap();
+    let root = tmp.path().join("root");
+
+    let mut visitor = SymlinkTestVisitor::default();
+    let options = WalkOptions {
+        follow_symlinks: true,
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+    walk_dir_with_options(&root, &mut visitor, &TestPathFilter::default(), &options);
+
+    // `link1` comes first by name and walks the directory, the rest are revisits
+    assert_eq!(visitor.dirs.len(), 1);
+    assert_eq!(visitor.files.len(), 1);
+    assert_eq!(
+        visitor.cycles,
+        vec![(root.join("link2"), 1), (root.join("shared"), 1)]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_report_their_target() {
+    let tmp = tempdir().unwrap();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::Dir("sub", vec![FsNode::File("file.txt", "hello")]),
+            FsNode::SymlinkFile("inside", "sub/file.txt"),
+        ],
+    );
+    create_fs_tree(tmp.path(), &tree).unwrap();
+    let root = tmp.path().join("root");
+    std::os::unix::fs::symlink("..", root.join("outside")).unwrap();
+    std::os::unix::fs::symlink("sub/missing.txt", root.join("dangling")).unwrap();
+    std::os::unix::fs::symlink("../missing.txt", root.join("dangling_outside")).unwrap();
+
+    for follow_symlinks in [false, true] {
+        let mut visitor = SymlinkTestVisitor::default();
+        let options = WalkOptions {
+            follow_symlinks,
+            max_depth: Some(1),
+            sort: SortBy::Name,
+            ..Default::default()
+        };
+        walk_dir_with_options(&root, &mut visitor, &TestPathFilter::default(), &options);
+
+        let link = |target: PathBuf, resolves, inside_root| SymlinkInfo {
+            target,
+            resolves,
+            inside_root,
+        };
+        assert_eq!(
+            visitor.links,
+            vec![
+                link(PathBuf::from("sub/missing.txt"), false, true),
+                link(PathBuf::from("../missing.txt"), false, false),
+                link(root.join("sub/file.txt"), true, true),
+                link(PathBuf::from(".."), true, false),
+            ]
+        );
+    }
+}
+
+#[cfg(any(target_os = "windows", unix))]
+#[test]
+fn logical_paths_keep_the_link_in_the_path() {
+    let tmp = tempdir().unwrap();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::Dir("real", vec![FsNode::File("file.txt", "hello")]),
+            FsNode::SymlinkDir("link", "real"),
+        ],
+    );
+    create_fs_tree(tmp.path(), &tree).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter {
+        ignored_paths: vec![root.join("real")],
+    };
+    let options = WalkOptions {
+        follow_symlinks: true,
+        logical_paths: true,
+        ..Default::default()
+    };
+
+    let files: Vec<_> = Walker::new(&root, &filter, &options)
+        .map(Result::unwrap)
+        .filter(|entry| entry.event() == WalkEvent::File)
+        .collect();
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].path(), root.join("link/file.txt"));
+    assert_eq!(
+        files[0].physical_path().unwrap(),
+        root.join("real/file.txt").canonicalize().unwrap()
+    );
 }
 
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", unix))]
 #[test]
 fn ignore_symlink_file() {
     // Setup temp dir
@@ -513,7 +641,7 @@ fn ignore_symlink_file() {
     );
 }
 
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", unix))]
 #[test]
 fn ignore_symlink_dir() {
     // Setup temp dir
@@ -572,3 +700,2181 @@ fn ignore_symlink_dir() {
             .contains(&(root.join("subdir").join("subdir2").join("dir_link"), 2))
     );
 }
+
+/*
+Parallel walk related tests
+*/
+impl ParallelFsVisitor for WalkTestVisitor {
+    fn fork(&self) -> Self {
+        Self::default()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.seen_files.extend(other.seen_files);
+        self.seen_dirs_enter.extend(other.seen_dirs_enter);
+        self.seen_dirs_exit.extend(other.seen_dirs_exit);
+    }
+}
+
+fn wide_tree() -> FsNode<'static> {
+    FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"),
+            FsNode::Dir(
+                "a",
+                vec![
+                    FsNode::File("a1.txt", "a"),
+                    FsNode::Dir("a2", vec![FsNode::File("a3.txt", "a")]),
+                ],
+            ),
+            FsNode::Dir(
+                "b",
+                vec![
+                    FsNode::File("b1.txt", "b"),
+                    FsNode::Dir(
+                        "b2",
+                        vec![FsNode::Dir("b3", vec![FsNode::File("b4.txt", "b")])],
+                    ),
+                ],
+            ),
+            FsNode::Dir("c", vec![FsNode::File("c1.txt", "c")]),
+        ],
+    )
+}
+
+#[test]
+fn parallel_walk_matches_sequential_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut sequential = WalkTestVisitor::default();
+    walk_dir(&root, &mut sequential, &filter, None, false);
+
+    let mut parallel = WalkTestVisitor::default();
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+    walk_dir_parallel(&root, &mut parallel, &filter, &options);
+
+    for visitor in [&mut sequential, &mut parallel] {
+        visitor.seen_files.sort();
+        visitor.seen_dirs_enter.sort();
+        visitor.seen_dirs_exit.sort();
+    }
+    assert_eq!(parallel.seen_files, sequential.seen_files);
+    assert_eq!(parallel.seen_dirs_enter, sequential.seen_dirs_enter);
+    assert_eq!(parallel.seen_dirs_exit, sequential.seen_dirs_exit);
+    assert_eq!(parallel.seen_files.len(), 6);
+    assert_eq!(parallel.seen_dirs_enter.len(), 6);
+}
+
+#[test]
+fn parallel_walk_respects_max_depth_and_filter() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter {
+        ignored_paths: vec![root.join("c")],
+    };
+
+    let mut visitor = WalkTestVisitor::default();
+    let options = WalkOptions {
+        max_depth: Some(2),
+        threads: 3,
+        ..Default::default()
+    };
+    walk_dir_parallel(&root, &mut visitor, &filter, &options);
+
+    visitor.seen_files.sort();
+    visitor.seen_dirs_enter.sort();
+    visitor.seen_dirs_exit.sort();
+    assert_eq!(visitor.seen_dirs_enter, visitor.seen_dirs_exit);
+    assert_eq!(
+        visitor.seen_files,
+        vec![
+            (root.join("a/a1.txt"), 2),
+            (root.join("b/b1.txt"), 2),
+            (root.join("file.txt"), 1),
+        ]
+    );
+    assert_eq!(
+        visitor.seen_dirs_enter,
+        vec![
+            (root.join("a"), 1),
+            (root.join("a/a2"), 2),
+            (root.join("b"), 1),
+            (root.join("b/b2"), 2),
+        ]
+    );
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Enter(PathBuf),
+    Exit(PathBuf),
+    File(PathBuf),
+}
+
+// Records events from all workers into a single log so that ordering can be checked
+#[derive(Default)]
+struct OrderingVisitor {
+    log: Arc<Mutex<Vec<Event>>>,
+}
+
+impl FsVisitor for OrderingVisitor {
+    fn visit_file(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.log
+            .lock()
+            .unwrap()
+            .push(Event::File(path.to_path_buf()));
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.log
+            .lock()
+            .unwrap()
+            .push(Event::Enter(path.to_path_buf()));
+        WalkControl::Continue
+    }
+
+    fn exit_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) {
+        self.log
+            .lock()
+            .unwrap()
+            .push(Event::Exit(path.to_path_buf()));
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+}
+
+impl ParallelFsVisitor for OrderingVisitor {
+    fn fork(&self) -> Self {
+        Self {
+            log: Arc::clone(&self.log),
+        }
+    }
+
+    fn merge(&mut self, _other: Self) {}
+}
+
+#[test]
+fn parallel_walk_exits_directories_after_their_subtree() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut visitor = OrderingVisitor::default();
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+    walk_dir_parallel(&root, &mut visitor, &filter, &options);
+
+    let log = visitor.log.lock().unwrap();
+    for (exit_idx, event) in log.iter().enumerate() {
+        let Event::Exit(dir) = event else {
+            continue;
+        };
+        // Everything below the directory must have been reported before it is exited
+        for (idx, other) in log.iter().enumerate() {
+            let (Event::Enter(p) | Event::Exit(p) | Event::File(p)) = other;
+            if p != dir && p.starts_with(dir) {
+                assert!(
+                    idx < exit_idx,
+                    "{:?} reported after exiting {:?}",
+                    other,
+                    dir
+                );
+            }
+        }
+    }
+    let exits = log.iter().filter(|e| matches!(e, Event::Exit(_))).count();
+    assert_eq!(exits, 6);
+}
+
+impl ParallelFsVisitor for SymlinkTestVisitor {
+    fn fork(&self) -> Self {
+        Self::default()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.files.extend(other.files);
+        self.dirs.extend(other.dirs);
+        self.symlinks.extend(other.symlinks);
+        self.links.extend(other.links);
+        self.cycles.extend(other.cycles);
+    }
+}
+
+#[cfg(any(target_os = "windows", unix))]
+#[test]
+fn parallel_symlink_cycle_does_not_revisit_directories() {
+    let tmp = tempdir().unwrap();
+    let tmp_path = tmp.path();
+
+    let tree = FsNode::Dir(
+        "a",
+        vec![
+            FsNode::Dir(
+                "b",
+                vec![FsNode::Dir(
+                    "c",
+                    vec![FsNode::SymlinkDir("d", "../../../a")],
+                )],
+            ),
+            FsNode::Dir("e", vec![FsNode::SymlinkDir("f", "../../a")]),
+        ],
+    );
+
+    create_fs_tree(tmp_path, &tree).unwrap();
+    let start = tmp_path.join("a/b");
+
+    let mut visitor = SymlinkTestVisitor::default();
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        follow_symlinks: true,
+        threads: 4,
+        ..Default::default()
+    };
+
+    walk_dir_parallel(&start, &mut visitor, &filter, &options);
+
+    let mut dirs = visitor.dirs.clone();
+    dirs.sort();
+    dirs.dedup();
+    assert_eq!(
+        dirs.len(),
+        visitor.dirs.len(),
+        "Directory visited more than once"
+    );
+    assert!(
+        visitor.dirs.iter().any(|(p, _)| p.ends_with("a")),
+        "Expected to reach directory 'a' via symlink"
+    );
+
+    // Back to the root through `a/b` and back to `a` through `e/f`
+    let mut cycles: Vec<_> = visitor.cycles.iter().map(|(p, _)| p.clone()).collect();
+    cycles.sort();
+    assert_eq!(cycles.len(), 2);
+    assert!(cycles[0].ends_with("a/b"));
+    assert!(cycles[1].ends_with("e/f"));
+}
+
+// Tracks the open directories on a stack, the way a depth-tracking visitor would,
+// and counts the exits that don't match the top of its own stack
+#[derive(Default)]
+struct DirStackVisitor {
+    stack: Vec<PathBuf>,
+    entered: Vec<PathBuf>,
+    exited: Vec<PathBuf>,
+    mismatched: usize,
+}
+
+impl FsVisitor for DirStackVisitor {
+    fn enter_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.stack.push(path.to_path_buf());
+        self.entered.push(path.to_path_buf());
+        WalkControl::Continue
+    }
+
+    fn exit_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) {
+        if self.stack.last().map(PathBuf::as_path) == Some(path) {
+            self.stack.pop();
+        } else {
+            self.mismatched += 1;
+        }
+        self.exited.push(path.to_path_buf());
+    }
+}
+
+impl ParallelFsVisitor for DirStackVisitor {
+    fn fork(&self) -> Self {
+        Self::default()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.stack.extend(other.stack);
+        self.entered.extend(other.entered);
+        self.exited.extend(other.exited);
+        self.mismatched += other.mismatched;
+    }
+}
+
+#[test]
+fn parallel_exits_pair_up_by_path_not_by_fork() {
+    let tmp = tempdir().unwrap();
+    for i in 0..20 {
+        let dir = tmp.path().join(format!("d{i}")).join("a").join("b");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file.txt"), "x").unwrap();
+    }
+    let filter = TestPathFilter::default();
+
+    // On one thread the callbacks nest
+    let mut visitor = DirStackVisitor::default();
+    walk_dir_parallel(tmp.path(), &mut visitor, &filter, &WalkOptions::default());
+    assert_eq!(visitor.mismatched, 0);
+    assert!(visitor.stack.is_empty());
+
+    // Across forks every directory is still exited exactly once, but not necessarily
+    // on the fork that entered it
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+    let mut visitor = DirStackVisitor::default();
+    walk_dir_parallel(tmp.path(), &mut visitor, &filter, &options);
+    visitor.entered.sort();
+    visitor.exited.sort();
+    assert_eq!(visitor.entered.len(), 60);
+    assert_eq!(visitor.entered, visitor.exited);
+}
+
+/*
+Walk control related tests
+*/
+#[derive(Default, Debug)]
+struct ControlTestVisitor {
+    skip_dir: Option<&'static str>,
+    marker_file: Option<&'static str>,
+    stop_at_file: Option<&'static str>,
+    files: Vec<PathBuf>,
+    dirs_enter: Vec<PathBuf>,
+    dirs_exit: Vec<PathBuf>,
+}
+
+impl FsVisitor for ControlTestVisitor {
+    fn visit_file(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.files.push(path.to_path_buf());
+        if self.stop_at_file.is_some_and(|name| path.ends_with(name)) {
+            return WalkControl::Stop;
+        }
+        if self.marker_file.is_some_and(|name| path.ends_with(name)) {
+            return WalkControl::SkipSubtree;
+        }
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.dirs_enter.push(path.to_path_buf());
+        if self.skip_dir.is_some_and(|name| path.ends_with(name)) {
+            return WalkControl::SkipSubtree;
+        }
+        WalkControl::Continue
+    }
+
+    fn exit_dir(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) {
+        self.dirs_exit.push(path.to_path_buf());
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+}
+
+impl ParallelFsVisitor for ControlTestVisitor {
+    fn fork(&self) -> Self {
+        Self {
+            skip_dir: self.skip_dir,
+            marker_file: self.marker_file,
+            stop_at_file: self.stop_at_file,
+            ..Default::default()
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.files.extend(other.files);
+        self.dirs_enter.extend(other.dirs_enter);
+        self.dirs_exit.extend(other.dirs_exit);
+    }
+}
+
+#[test]
+fn skip_subtree_from_enter_dir_does_not_descend() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let mut visitor = ControlTestVisitor {
+            skip_dir: Some("b"),
+            ..Default::default()
+        };
+        let options = WalkOptions {
+            threads,
+            ..Default::default()
+        };
+        walk_dir_parallel(&root, &mut visitor, &filter, &options);
+
+        // The skipped directory is still entered and exited, but nothing below it is seen
+        assert!(visitor.dirs_enter.contains(&root.join("b")));
+        assert!(visitor.dirs_exit.contains(&root.join("b")));
+        assert!(
+            !visitor
+                .dirs_enter
+                .iter()
+                .any(|p| p.starts_with(root.join("b/b2")))
+        );
+        assert!(!visitor.files.iter().any(|p| p.starts_with(root.join("b"))));
+        assert_eq!(visitor.files.len(), 4);
+    }
+}
+
+#[test]
+fn skip_subtree_from_visit_file_skips_rest_of_directory() {
+    let tmp = tempdir().unwrap();
+    let tree = FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file.txt", "hello"),
+            FsNode::Dir(
+                "marked",
+                vec![
+                    FsNode::File(".marker", ""),
+                    FsNode::File("a.txt", "a"),
+                    FsNode::File("b.txt", "b"),
+                    FsNode::File("c.txt", "c"),
+                ],
+            ),
+        ],
+    );
+    create_fs_tree(tmp.path(), &tree).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut visitor = ControlTestVisitor {
+        marker_file: Some(".marker"),
+        ..Default::default()
+    };
+    walk_dir(&root, &mut visitor, &filter, None, false);
+
+    // Nothing in `marked` is reported after the marker, whatever order read_dir uses
+    let marked: Vec<_> = visitor
+        .files
+        .iter()
+        .filter(|p| p.starts_with(root.join("marked")))
+        .collect();
+    assert_eq!(marked.last().unwrap(), &&root.join("marked/.marker"));
+    assert!(visitor.files.contains(&root.join("file.txt")));
+    assert_eq!(visitor.dirs_exit, vec![root.join("marked")]);
+}
+
+#[test]
+fn stop_ends_the_walk_immediately() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut visitor = ControlTestVisitor {
+        stop_at_file: Some("a3.txt"),
+        ..Default::default()
+    };
+    walk_dir(&root, &mut visitor, &filter, None, false);
+
+    // The stopping file is the last thing reported and no open directory is exited
+    assert_eq!(visitor.files.last().unwrap(), &root.join("a/a2/a3.txt"));
+    assert!(!visitor.dirs_exit.contains(&root.join("a")));
+    assert!(!visitor.dirs_exit.contains(&root.join("a/a2")));
+}
+
+#[test]
+fn stop_ends_a_parallel_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut visitor = ControlTestVisitor {
+        stop_at_file: Some("a3.txt"),
+        ..Default::default()
+    };
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+    walk_dir_parallel(&root, &mut visitor, &filter, &options);
+
+    assert!(visitor.files.contains(&root.join("a/a2/a3.txt")));
+    assert!(!visitor.dirs_exit.contains(&root.join("a")));
+    assert!(!visitor.dirs_exit.contains(&root.join("a/a2")));
+}
+
+/*
+Walker iterator related tests
+*/
+fn walker_events(
+    root: &Path,
+    filter: &dyn PathFilter,
+    options: &WalkOptions,
+) -> Vec<(WalkEvent, PathBuf, usize)> {
+    Walker::new(root, filter, options)
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.event(), entry.path().to_path_buf(), entry.depth())
+        })
+        .collect()
+}
+
+#[test]
+fn walker_yields_enter_and_exit_around_directory_contents() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let events = walker_events(&root, &filter, &WalkOptions::default());
+
+    assert_eq!(events.len(), 6 + 6 * 2);
+    for (idx, (event, path, depth)) in events.iter().enumerate() {
+        if *event != WalkEvent::EnterDir {
+            continue;
+        }
+        // Everything up to the matching exit is inside the directory and one level deeper
+        let exit = events[idx..]
+            .iter()
+            .position(|(e, p, _)| *e == WalkEvent::ExitDir && p == path)
+            .unwrap()
+            + idx;
+        assert_eq!(events[exit].2, *depth);
+        for (_, inner, inner_depth) in &events[idx + 1..exit] {
+            assert!(inner.starts_with(path));
+            assert!(*inner_depth > *depth);
+        }
+    }
+}
+
+#[test]
+fn walker_matches_walk_dir() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter {
+        ignored_paths: vec![root.join("a/a2")],
+    };
+    let options = WalkOptions {
+        max_depth: Some(2),
+        ..Default::default()
+    };
+
+    let mut visitor = WalkTestVisitor::default();
+    walk_dir(&root, &mut visitor, &filter, Some(2), false);
+    let events = walker_events(&root, &filter, &options);
+
+    let collect = |kind: WalkEvent| -> Vec<(PathBuf, usize)> {
+        events
+            .iter()
+            .filter(|(e, _, _)| *e == kind)
+            .map(|(_, p, d)| (p.clone(), *d))
+            .collect()
+    };
+    assert_eq!(collect(WalkEvent::File), visitor.seen_files);
+    assert_eq!(collect(WalkEvent::EnterDir), visitor.seen_dirs_enter);
+    assert_eq!(collect(WalkEvent::ExitDir), visitor.seen_dirs_exit);
+}
+
+#[test]
+fn walker_skip_current_dir() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut walker = Walker::new(&root, &filter, &WalkOptions::default());
+    let mut files = Vec::new();
+    let mut exits = Vec::new();
+    while let Some(entry) = walker.next() {
+        let entry = entry.unwrap();
+        match entry.event() {
+            WalkEvent::EnterDir if entry.path() == root.join("b") => walker.skip_current_dir(),
+            WalkEvent::File => files.push(entry.into_path()),
+            WalkEvent::ExitDir => exits.push(entry.into_path()),
+            _ => {}
+        }
+    }
+
+    assert!(!files.iter().any(|p| p.starts_with(root.join("b"))));
+    assert!(exits.contains(&root.join("b")));
+    assert_eq!(files.len(), 4);
+}
+
+#[test]
+fn walker_composes_with_iterator_adapters() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let sizes: Vec<u64> = Walker::new(&root, &filter, &WalkOptions::default())
+        .filter_map(Result::ok)
+        .filter(|entry| entry.event() == WalkEvent::File)
+        .map(|entry| entry.metadata().unwrap().len())
+        .take(3)
+        .collect();
+
+    assert_eq!(sizes.len(), 3);
+}
+
+#[test]
+fn walker_reports_unreadable_root() {
+    let tmp = tempdir().unwrap();
+    let filter = TestPathFilter::default();
+
+    let mut walker = Walker::new(
+        &tmp.path().join("missing"),
+        &filter,
+        &WalkOptions::default(),
+    );
+
+    assert!(matches!(walker.next(), Some(Err(FsError::Io(_, _)))));
+    assert!(walker.next().is_none());
+}
+
+#[test]
+fn walker_handles_deep_trees_on_a_small_stack() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path().join("root");
+    let mut deepest = root.clone();
+    for _ in 0..1000 {
+        deepest.push("d");
+    }
+    std::fs::create_dir_all(&deepest).unwrap();
+    std::fs::write(deepest.join("file.txt"), "deep").unwrap();
+
+    // A recursive walk needs a stack frame per level, the walker doesn't
+    let max_depth = std::thread::Builder::new()
+        .stack_size(64 * 1024)
+        .spawn(move || {
+            let filter = TestPathFilter::default();
+            Walker::new(&root, &filter, &WalkOptions::default())
+                .map(|entry| entry.unwrap().depth())
+                .max()
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    assert_eq!(max_depth, Some(1001));
+}
+
+/*
+Sorting related tests
+*/
+#[test]
+fn natural_cmp_orders_numbers_by_value() {
+    use std::cmp::Ordering;
+
+    assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+    assert_eq!(natural_cmp("file10", "file2"), Ordering::Greater);
+    assert_eq!(natural_cmp("a", "b"), Ordering::Less);
+    assert_eq!(natural_cmp("file", "file1"), Ordering::Less);
+    assert_eq!(natural_cmp("v1.10", "v1.9"), Ordering::Greater);
+    assert_eq!(natural_cmp("file1", "file1"), Ordering::Equal);
+    // Equal numbers with different leading zeros still get a stable order
+    assert_ne!(natural_cmp("file01", "file1"), Ordering::Equal);
+}
+
+fn sortable_tree() -> FsNode<'static> {
+    FsNode::Dir(
+        "root",
+        vec![
+            FsNode::File("file10.txt", "a"),
+            FsNode::File("file2.txt", "aaa"),
+            FsNode::File("file1.txt", "aa"),
+            FsNode::Dir("dir", vec![FsNode::File("b", "bb"), FsNode::File("a", "a")]),
+        ],
+    )
+}
+
+fn file_names(root: &Path, options: &WalkOptions) -> Vec<String> {
+    let filter = TestPathFilter::default();
+    Walker::new(root, &filter, options)
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.event() != WalkEvent::ExitDir)
+        .map(|entry| {
+            entry
+                .path()
+                .strip_prefix(root)
+                .unwrap()
+                .display()
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn sorts_entries_by_name() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &sortable_tree()).unwrap();
+    let root = tmp.path().join("root");
+
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+    assert_eq!(
+        file_names(&root, &options),
+        vec![
+            "dir",
+            "dir/a",
+            "dir/b",
+            "file1.txt",
+            "file2.txt",
+            "file10.txt"
+        ]
+    );
+
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        sort_reverse: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        file_names(&root, &options),
+        vec![
+            "file10.txt",
+            "file2.txt",
+            "file1.txt",
+            "dir",
+            "dir/b",
+            "dir/a"
+        ]
+    );
+}
+
+#[test]
+fn sorts_entries_by_size() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(
+        tmp.path(),
+        &FsNode::Dir(
+            "root",
+            vec![
+                FsNode::File("file10.txt", "a"),
+                FsNode::File("file2.txt", "aaa"),
+                FsNode::File("file1.txt", "aa"),
+            ],
+        ),
+    )
+    .unwrap();
+    let root = tmp.path().join("root");
+
+    let options = WalkOptions {
+        sort: SortBy::Size,
+        ..Default::default()
+    };
+    assert_eq!(
+        file_names(&root, &options),
+        vec!["file10.txt", "file1.txt", "file2.txt"]
+    );
+}
+
+#[test]
+fn sorts_entries_with_custom_comparator() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &sortable_tree()).unwrap();
+    let root = tmp.path().join("root");
+
+    // Files before directories, then by plain byte order
+    let options = WalkOptions {
+        sort: SortBy::Custom(Arc::new(|a, b| {
+            let is_dir = |e: &fsx::WalkEntry| e.file_type().is_dir();
+            is_dir(a)
+                .cmp(&is_dir(b))
+                .then_with(|| a.path().cmp(b.path()))
+        })),
+        ..Default::default()
+    };
+    assert_eq!(
+        file_names(&root, &options),
+        vec![
+            "file1.txt",
+            "file10.txt",
+            "file2.txt",
+            "dir",
+            "dir/a",
+            "dir/b"
+        ]
+    );
+}
+
+#[test]
+fn sorted_walk_dir_reports_in_order() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &sortable_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut visitor = WalkTestVisitor::default();
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+    walk_dir_with_options(&root, &mut visitor, &filter, &options);
+
+    assert_eq!(
+        visitor.seen_files,
+        vec![
+            (root.join("dir/a"), 2),
+            (root.join("dir/b"), 2),
+            (root.join("file1.txt"), 1),
+            (root.join("file2.txt"), 1),
+            (root.join("file10.txt"), 1),
+        ]
+    );
+}
+
+/*
+Breadth first related tests
+*/
+#[test]
+fn breadth_first_walker_visits_shallow_entries_first() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        strategy: WalkStrategy::BreadthFirst,
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let events: Vec<_> = walker_events(&root, &filter, &options)
+        .into_iter()
+        .map(|(event, path, depth)| {
+            (
+                event,
+                path.strip_prefix(&root).unwrap().to_path_buf(),
+                depth,
+            )
+        })
+        .collect();
+
+    let expected = vec![
+        (WalkEvent::File, "file.txt", 1),
+        (WalkEvent::EnterDir, "a", 1),
+        (WalkEvent::File, "a/a1.txt", 2),
+        (WalkEvent::ExitDir, "a", 1),
+        (WalkEvent::EnterDir, "b", 1),
+        (WalkEvent::File, "b/b1.txt", 2),
+        (WalkEvent::ExitDir, "b", 1),
+        (WalkEvent::EnterDir, "c", 1),
+        (WalkEvent::File, "c/c1.txt", 2),
+        (WalkEvent::ExitDir, "c", 1),
+        (WalkEvent::EnterDir, "a/a2", 2),
+        (WalkEvent::File, "a/a2/a3.txt", 3),
+        (WalkEvent::ExitDir, "a/a2", 2),
+        (WalkEvent::EnterDir, "b/b2", 2),
+        (WalkEvent::ExitDir, "b/b2", 2),
+        (WalkEvent::EnterDir, "b/b2/b3", 3),
+        (WalkEvent::File, "b/b2/b3/b4.txt", 4),
+        (WalkEvent::ExitDir, "b/b2/b3", 3),
+    ];
+    let expected: Vec<_> = expected
+        .into_iter()
+        .map(|(event, path, depth)| (event, PathBuf::from(path), depth))
+        .collect();
+    assert_eq!(events, expected);
+}
+
+#[test]
+fn breadth_first_walk_dir_respects_max_depth_and_skips() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    let mut visitor = ControlTestVisitor {
+        skip_dir: Some("a"),
+        ..Default::default()
+    };
+    let options = WalkOptions {
+        max_depth: Some(2),
+        strategy: WalkStrategy::BreadthFirst,
+        ..Default::default()
+    };
+    walk_dir_with_options(&root, &mut visitor, &filter, &options);
+
+    visitor.files.sort();
+    visitor.dirs_enter.sort();
+    visitor.dirs_exit.sort();
+    assert_eq!(
+        visitor.files,
+        vec![
+            root.join("b/b1.txt"),
+            root.join("c/c1.txt"),
+            root.join("file.txt")
+        ]
+    );
+    assert_eq!(
+        visitor.dirs_enter,
+        vec![
+            root.join("a"),
+            root.join("b"),
+            root.join("b/b2"),
+            root.join("c")
+        ]
+    );
+    assert_eq!(visitor.dirs_enter, visitor.dirs_exit);
+}
+
+/*
+One file system related tests
+*/
+#[cfg(unix)]
+#[derive(Default)]
+struct MountPointTestVisitor {
+    files: Vec<PathBuf>,
+    mount_points: Vec<PathBuf>,
+}
+
+#[cfg(unix)]
+impl FsVisitor for MountPointTestVisitor {
+    fn visit_file(&mut self, path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.files.push(path.to_path_buf());
+        WalkControl::Continue
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+
+    fn skip_mount_point(&mut self, path: &Path, _depth: usize) {
+        self.mount_points.push(path.to_path_buf());
+    }
+}
+
+#[cfg(unix)]
+impl ParallelFsVisitor for MountPointTestVisitor {
+    fn fork(&self) -> Self {
+        Self::default()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.files.extend(other.files);
+        self.mount_points.extend(other.mount_points);
+    }
+}
+
+// A tree with a symlink to a directory on another filesystem. Returns `None` when
+// no second filesystem is available to link to.
+#[cfg(unix)]
+fn tree_with_other_filesystem() -> Option<(tempfile::TempDir, tempfile::TempDir)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmp = tempdir().unwrap();
+    let other = tempfile::tempdir_in("/dev/shm").ok()?;
+    let dev = |p: &Path| std::fs::metadata(p).unwrap().dev();
+    if dev(tmp.path()) == dev(other.path()) {
+        return None;
+    }
+    std::fs::write(other.path().join("elsewhere.txt"), "x").unwrap();
+    std::fs::write(tmp.path().join("here.txt"), "x").unwrap();
+    std::os::unix::fs::symlink(other.path(), tmp.path().join("mnt")).unwrap();
+    Some((tmp, other))
+}
+
+#[cfg(unix)]
+#[test]
+fn one_file_system_does_not_enter_other_filesystems() {
+    let Some((tmp, other)) = tree_with_other_filesystem() else {
+        return;
+    };
+    let filter = TestPathFilter::default();
+    let target = other.path().canonicalize().unwrap();
+
+    let mut visitor = MountPointTestVisitor::default();
+    let options = WalkOptions {
+        follow_symlinks: true,
+        one_file_system: true,
+        ..Default::default()
+    };
+    walk_dir_with_options(tmp.path(), &mut visitor, &filter, &options);
+    assert_eq!(visitor.files, vec![tmp.path().join("here.txt")]);
+    assert_eq!(visitor.mount_points, vec![target.clone()]);
+
+    let mut visitor = MountPointTestVisitor::default();
+    walk_dir_parallel(
+        tmp.path(),
+        &mut visitor,
+        &filter,
+        &WalkOptions {
+            threads: 4,
+            ..options
+        },
+    );
+    assert_eq!(visitor.files, vec![tmp.path().join("here.txt")]);
+    assert_eq!(visitor.mount_points, vec![target]);
+}
+
+#[cfg(unix)]
+#[test]
+fn other_filesystems_are_entered_by_default() {
+    let Some((tmp, other)) = tree_with_other_filesystem() else {
+        return;
+    };
+    let filter = TestPathFilter::default();
+
+    let mut visitor = MountPointTestVisitor::default();
+    let options = WalkOptions {
+        follow_symlinks: true,
+        ..Default::default()
+    };
+    walk_dir_with_options(tmp.path(), &mut visitor, &filter, &options);
+    visitor.files.sort();
+    let mut expected = vec![
+        tmp.path().join("here.txt"),
+        other.path().canonicalize().unwrap().join("elsewhere.txt"),
+    ];
+    expected.sort();
+    assert_eq!(visitor.files, expected);
+    assert!(visitor.mount_points.is_empty());
+}
+
+/*
+Min depth related tests
+*/
+#[test]
+fn min_depth_walks_but_does_not_report_shallow_entries() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        min_depth: Some(2),
+        max_depth: Some(2),
+        ..Default::default()
+    };
+
+    let events = walker_events(&root, &filter, &options);
+    let mut paths: Vec<_> = events
+        .iter()
+        .filter(|(event, _, _)| *event != WalkEvent::ExitDir)
+        .map(|(_, path, depth)| (path.strip_prefix(&root).unwrap().to_path_buf(), *depth))
+        .collect();
+    paths.sort();
+    let expected: Vec<_> = ["a/a1.txt", "a/a2", "b/b1.txt", "b/b2", "c/c1.txt"]
+        .into_iter()
+        .map(|p| (PathBuf::from(p), 2))
+        .collect();
+    assert_eq!(paths, expected);
+
+    let mut sequential = WalkTestVisitor::default();
+    walk_dir_with_options(&root, &mut sequential, &filter, &options);
+    let mut parallel = WalkTestVisitor::default();
+    walk_dir_parallel(
+        &root,
+        &mut parallel,
+        &filter,
+        &WalkOptions {
+            threads: 4,
+            ..options
+        },
+    );
+    for visitor in [&mut sequential, &mut parallel] {
+        visitor.seen_files.sort();
+        visitor.seen_dirs_enter.sort();
+        visitor.seen_dirs_exit.sort();
+        assert_eq!(visitor.seen_files.len(), 3);
+        assert_eq!(
+            visitor.seen_dirs_enter,
+            vec![(root.join("a/a2"), 2), (root.join("b/b2"), 2)]
+        );
+        assert_eq!(visitor.seen_dirs_enter, visitor.seen_dirs_exit);
+    }
+}
+
+/*
+Walk limit related tests
+*/
+#[derive(Default)]
+struct CountingVisitor {
+    reported: usize,
+    cancel_after: Option<(usize, CancelToken)>,
+}
+
+impl FsVisitor for CountingVisitor {
+    fn visit_file(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.reported += 1;
+        if let Some((after, token)) = &self.cancel_after
+            && self.reported >= *after
+        {
+            token.cancel();
+        }
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.reported += 1;
+        WalkControl::Continue
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+}
+
+impl ParallelFsVisitor for CountingVisitor {
+    fn fork(&self) -> Self {
+        Self {
+            reported: 0,
+            cancel_after: None,
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.reported += other.reported;
+    }
+}
+
+#[test]
+fn max_entries_stops_after_that_many_entries() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let options = WalkOptions {
+            max_entries: Some(5),
+            threads,
+            ..Default::default()
+        };
+        let mut visitor = CountingVisitor::default();
+        let partial = walk_dir_parallel(&root, &mut visitor, &filter, &options);
+        assert_eq!(partial, Some(PartialReason::MaxEntries));
+        assert_eq!(visitor.reported, 5);
+    }
+
+    // A limit that isn't reached leaves the walk complete
+    let options = WalkOptions {
+        max_entries: Some(12),
+        ..Default::default()
+    };
+    let mut visitor = CountingVisitor::default();
+    let partial = walk_dir_with_options(&root, &mut visitor, &filter, &options);
+    assert_eq!(partial, None);
+    assert_eq!(visitor.reported, 12);
+}
+
+#[test]
+fn cancel_token_stops_the_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+    let token = CancelToken::new();
+    let options = WalkOptions {
+        cancel: Some(token.clone()),
+        ..Default::default()
+    };
+
+    let mut visitor = CountingVisitor {
+        cancel_after: Some((1, token.clone())),
+        ..Default::default()
+    };
+    let partial = walk_dir_with_options(&root, &mut visitor, &filter, &options);
+    assert_eq!(partial, Some(PartialReason::Cancelled));
+    assert!(visitor.reported < 12);
+
+    // A cancelled token stops the walk before anything is reported
+    let mut walker = Walker::new(&root, &filter, &options);
+    assert!(walker.next().is_none());
+    assert_eq!(walker.partial(), Some(PartialReason::Cancelled));
+}
+
+#[test]
+fn child_cancel_token_follows_its_parent_only() {
+    let parent = CancelToken::new();
+    let child = parent.child();
+    child.cancel();
+    assert!(!parent.is_cancelled());
+
+    let child = parent.child();
+    parent.cancel();
+    assert!(child.is_cancelled());
+}
+
+#[test]
+fn deadline_in_the_past_stops_the_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        deadline: Some(std::time::Instant::now()),
+        threads: 4,
+        ..Default::default()
+    };
+
+    let mut visitor = CountingVisitor::default();
+    let partial = walk_dir_parallel(&root, &mut visitor, &filter, &options);
+    assert_eq!(partial, Some(PartialReason::Deadline));
+    assert_eq!(visitor.reported, 0);
+}
+
+#[test]
+fn fail_fast_walker_stops_at_the_first_error() {
+    let tmp = tempdir().unwrap();
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        error_policy: ErrorPolicy::FailFast,
+        ..Default::default()
+    };
+
+    let mut walker = Walker::new(&tmp.path().join("missing"), &filter, &options);
+    assert!(matches!(walker.next(), Some(Err(FsError::Io(_, _)))));
+    assert!(walker.next().is_none());
+    assert_eq!(walker.partial(), Some(PartialReason::Error));
+
+    // Ignored errors are not yielded at all
+    let options = WalkOptions {
+        error_policy: ErrorPolicy::Ignore,
+        ..Default::default()
+    };
+    let mut walker = Walker::new(&tmp.path().join("missing"), &filter, &options);
+    assert!(walker.next().is_none());
+    assert_eq!(walker.partial(), None);
+}
+
+/*
+Progress related tests
+*/
+fn progress_log(interval: Duration) -> (ProgressReporter, Arc<Mutex<Vec<Progress>>>) {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let reports = Arc::clone(&log);
+    let reporter = ProgressReporter::new(interval, move |progress: &Progress| {
+        reports.lock().unwrap().push(progress.clone());
+    });
+    (reporter, log)
+}
+
+#[test]
+fn progress_is_reported_while_walking_and_at_the_end() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let (reporter, log) = progress_log(Duration::ZERO);
+        let options = WalkOptions {
+            threads,
+            progress: Some(reporter),
+            ..Default::default()
+        };
+        walk_dir_parallel(&root, &mut WalkTestVisitor::default(), &filter, &options);
+
+        let log = log.lock().unwrap();
+        let last = log.last().unwrap();
+        assert!(last.done);
+        assert_eq!(last.entries, 12);
+        assert_eq!(last.bytes, Some(10));
+        assert_eq!(last.errors, 0);
+        assert!(log[..log.len() - 1].iter().all(|p| !p.done));
+        assert!(
+            log[..log.len() - 1]
+                .iter()
+                .all(|p| p.current_dir.starts_with(&root))
+        );
+        // Reports can be skipped when workers race for them
+        if threads == 1 {
+            assert_eq!(log.len(), 13);
+        }
+    }
+}
+
+#[test]
+fn progress_reports_are_throttled() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+
+    let (reporter, log) = progress_log(Duration::from_secs(3600));
+    let options = WalkOptions {
+        progress: Some(reporter),
+        ..Default::default()
+    };
+    walk_dir_with_options(
+        &root,
+        &mut WalkTestVisitor::default(),
+        &TestPathFilter::default(),
+        &options,
+    );
+
+    // Only the final report is made
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 1);
+    assert!(log[0].done);
+    assert_eq!(log[0].entries, 12);
+}
+
+#[test]
+fn progress_only_has_bytes_when_the_visitor_needs_sizes() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+
+    for threads in [1, 4] {
+        for (needs, bytes) in [
+            (MetadataNeeds::Full, Some(10)),
+            (MetadataNeeds::Size, Some(10)),
+            (MetadataNeeds::FileType, None),
+        ] {
+            let (reporter, log) = progress_log(Duration::ZERO);
+            let options = WalkOptions {
+                threads,
+                progress: Some(reporter),
+                ..Default::default()
+            };
+            let mut visitor = FetchedProbe::new(needs);
+            walk_dir_parallel(&root, &mut visitor, &TestPathFilter::default(), &options);
+
+            let log = log.lock().unwrap();
+            assert!(log.iter().all(|p| p.bytes.is_some() == bytes.is_some()));
+            assert_eq!(log.last().unwrap().bytes, bytes, "{needs:?}");
+        }
+    }
+}
+
+/*
+Metadata needs related tests
+*/
+// 10 directories with 50 files each, only one of which is called `needle.txt`
+fn large_tree(root: &Path) {
+    for dir in 0..10 {
+        let dir = root.join(format!("dir{dir}"));
+        std::fs::create_dir(&dir).unwrap();
+        for file in 0..50 {
+            std::fs::write(dir.join(format!("file{file}.txt")), "x").unwrap();
+        }
+    }
+    std::fs::write(root.join("dir3").join("needle.txt"), "needle").unwrap();
+}
+
+// Records, for every file and directory, whether its metadata had been fetched
+// before the callback
+struct FetchedProbe {
+    needs: MetadataNeeds,
+    files: Vec<bool>,
+    dirs: Vec<bool>,
+}
+
+impl FetchedProbe {
+    fn new(needs: MetadataNeeds) -> Self {
+        Self {
+            needs,
+            files: Vec::new(),
+            dirs: Vec::new(),
+        }
+    }
+}
+
+impl FsVisitor for FetchedProbe {
+    fn visit_file(&mut self, _path: &Path, meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.files.push(meta.cached().is_some());
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, _path: &Path, meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.dirs.push(meta.cached().is_some());
+        WalkControl::Continue
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        self.needs
+    }
+}
+
+impl ParallelFsVisitor for FetchedProbe {
+    fn fork(&self) -> Self {
+        Self::new(self.needs)
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.files.extend(other.files);
+        self.dirs.extend(other.dirs);
+    }
+}
+
+#[test]
+fn visitors_only_pay_for_the_metadata_they_need() {
+    let tmp = tempdir().unwrap();
+    large_tree(tmp.path());
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let options = WalkOptions {
+            threads,
+            ..Default::default()
+        };
+        // (needs, files fetched, directories fetched)
+        for (needs, files, dirs) in [
+            (MetadataNeeds::Full, true, true),
+            (MetadataNeeds::Size, true, false),
+            (MetadataNeeds::FileType, false, false),
+        ] {
+            let mut probe = FetchedProbe::new(needs);
+            walk_dir_parallel(tmp.path(), &mut probe, &filter, &options);
+            assert_eq!(probe.files.len(), 501);
+            assert_eq!(probe.dirs.len(), 10);
+            assert!(probe.files.iter().all(|&f| f == files), "{needs:?}");
+            assert!(probe.dirs.iter().all(|&d| d == dirs), "{needs:?}");
+        }
+    }
+
+    // On one thread every lookup is counted
+    for dir_fds in [false, true] {
+        let options = WalkOptions {
+            dir_fds,
+            ..Default::default()
+        };
+        let mut calls = Vec::new();
+        for needs in [
+            MetadataNeeds::FileType,
+            MetadataNeeds::Size,
+            MetadataNeeds::Full,
+        ] {
+            let before = stat_calls();
+            walk_dir_parallel(tmp.path(), &mut FetchedProbe::new(needs), &filter, &options);
+            calls.push(stat_calls() - before);
+        }
+        // Nothing beyond the listings, the 501 files, and the 10 directories as well
+        assert_eq!(calls, [0, 501, 511], "dir_fds: {dir_fds}");
+    }
+
+    // Statistics need the size of every file, a name-only search goes by the
+    // directory listing and only looks up its matches
+    assert_eq!(
+        StatsVisitor::default().metadata_needs(),
+        MetadataNeeds::Size
+    );
+    let find = FindVisitor::new(r"^needle\.txt$", FindType::File).unwrap();
+    assert_eq!(find.metadata_needs(), MetadataNeeds::FileType);
+    let report = find_with_options(
+        tmp.path(),
+        &filter,
+        r"^needle\.txt$",
+        &WalkOptions::default(),
+    );
+    assert_eq!(report.entries.len(), 1);
+    assert_eq!(report.entries[0].size, 6);
+    let report = collect_with_options(tmp.path(), &filter, &WalkOptions::default());
+    assert_eq!(report.stats.total_files, 501);
+    assert_eq!(report.stats.total_size, 506);
+}
+
+#[derive(Default)]
+struct FileTypeVisitor {
+    dirs: usize,
+    sizes: Vec<u64>,
+}
+
+impl FsVisitor for FileTypeVisitor {
+    fn visit_file(&mut self, _path: &Path, meta: &EntryMeta, _depth: usize) -> WalkControl {
+        assert!(meta.is_file());
+        assert!(meta.cached().is_none());
+        self.sizes.push(meta.size().unwrap());
+        assert!(meta.cached().is_some());
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, _path: &Path, meta: &EntryMeta, _depth: usize) -> WalkControl {
+        assert!(meta.is_dir());
+        assert!(meta.cached().is_none());
+        self.dirs += 1;
+        WalkControl::Continue
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+
+    fn metadata_needs(&self) -> MetadataNeeds {
+        MetadataNeeds::FileType
+    }
+}
+
+#[test]
+fn metadata_is_fetched_on_demand() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+
+    // Nothing is fetched before it is asked for, see `FileTypeVisitor`
+    let mut visitor = FileTypeVisitor::default();
+    walk_dir_with_options(
+        &root,
+        &mut visitor,
+        &TestPathFilter::default(),
+        &WalkOptions::default(),
+    );
+    assert_eq!(visitor.dirs, 6);
+    assert_eq!(visitor.sizes.len(), 6);
+    assert_eq!(visitor.sizes.iter().sum::<u64>(), 10);
+}
+
+/*
+Directory fd related tests
+*/
+#[cfg(target_os = "linux")]
+#[test]
+fn dir_fd_walk_matches_path_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    create_fs_tree(&tmp.path().join("root"), &FsNode::SymlinkDir("link", "a")).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let walk = |dir_fds| {
+            let options = WalkOptions {
+                threads,
+                follow_symlinks: true,
+                dir_fds,
+                ..Default::default()
+            };
+            let mut visitor = WalkTestVisitor::default();
+            walk_dir_parallel(&root, &mut visitor, &filter, &options);
+            visitor.seen_files.sort();
+            visitor.seen_dirs_enter.sort();
+            visitor.seen_dirs_exit.sort();
+            let stats = collect_with_options(&root, &filter, &options).stats;
+            (visitor, stats)
+        };
+        let (by_path, path_stats) = walk(false);
+        let (by_fd, fd_stats) = walk(true);
+
+        assert_eq!(by_fd.seen_files, by_path.seen_files);
+        assert_eq!(by_fd.seen_dirs_enter, by_path.seen_dirs_enter);
+        assert_eq!(by_fd.seen_dirs_exit, by_path.seen_dirs_exit);
+        assert_eq!(by_fd.seen_files.len(), 6);
+        assert_eq!(fd_stats.total_size, path_stats.total_size);
+        assert_eq!(fd_stats.total_symlinks, 1);
+    }
+}
+
+// The size and inode of every file and directory, as fetched before the callbacks
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct MetaRecorder {
+    seen: Vec<(PathBuf, u64, u64)>,
+}
+
+#[cfg(target_os = "linux")]
+impl MetaRecorder {
+    fn record(&mut self, path: &Path, meta: &EntryMeta) {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = meta.cached().expect("fetched before the callback");
+        self.seen.push((path.to_path_buf(), meta.len(), meta.ino()));
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FsVisitor for MetaRecorder {
+    fn visit_file(&mut self, path: &Path, meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.record(path, meta);
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, path: &Path, meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.record(path, meta);
+        WalkControl::Continue
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn dir_fd_metadata_is_the_same_with_and_without_proc() {
+    use fsx::test_utils::without_proc_fd;
+    use std::os::unix::fs::MetadataExt;
+
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        dir_fds: true,
+        ..Default::default()
+    };
+    let walk = || {
+        let mut visitor = MetaRecorder::default();
+        walk_dir_with_options(&root, &mut visitor, &filter, &options);
+        visitor.seen.sort();
+        visitor.seen
+    };
+
+    let before = stat_calls();
+    let with_proc = walk();
+    let without_proc = without_proc_fd(walk);
+    // Both look up every entry once
+    assert_eq!(stat_calls() - before, 2 * 12);
+
+    let expected: Vec<_> = with_proc
+        .iter()
+        .map(|(path, _, _)| {
+            let meta = std::fs::symlink_metadata(path).unwrap();
+            (path.clone(), meta.len(), meta.ino())
+        })
+        .collect();
+    assert_eq!(with_proc.len(), 12);
+    assert_eq!(with_proc, expected);
+    assert_eq!(without_proc, expected);
+}
+
+// Create `levels` nested directories with long names in `root`, the innermost
+// holding `file.txt`. The full paths get longer than PATH_MAX, so the tree is built
+// relative to directory descriptors.
+#[cfg(target_os = "linux")]
+fn deep_tree(root: &Path, levels: usize) {
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let name = CString::new("d".repeat(200)).unwrap();
+    let mut dir = File::open(root).unwrap();
+    for _ in 0..levels {
+        // SAFETY: `name` is a valid NUL terminated string and `dir` is open
+        let fd = unsafe {
+            assert_eq!(libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o755), 0);
+            libc::openat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY,
+            )
+        };
+        assert!(fd >= 0);
+        // SAFETY: `fd` was just opened
+        dir = unsafe { File::from_raw_fd(fd) };
+    }
+    let file_name = CString::new("file.txt").unwrap();
+    // SAFETY: `file_name` is a valid NUL terminated string and `dir` is open
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            file_name.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT,
+            0o644,
+        )
+    };
+    assert!(fd >= 0);
+    // SAFETY: `fd` was just opened
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(b"deep").unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn dir_fd_walk_handles_paths_longer_than_path_max() {
+    let tmp = tempdir().unwrap();
+    deep_tree(tmp.path(), 25);
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        dir_fds: true,
+        ..Default::default()
+    };
+
+    let mut visitor = WalkTestVisitor::default();
+    walk_dir_with_options(tmp.path(), &mut visitor, &filter, &options);
+    assert_eq!(visitor.seen_dirs_enter.len(), 25);
+    assert_eq!(visitor.seen_dirs_exit.len(), 25);
+    let (file, depth) = &visitor.seen_files[0];
+    assert_eq!(*depth, 26);
+    assert!(file.as_os_str().len() > libc::PATH_MAX as usize);
+    assert!(file.ends_with("file.txt"));
+
+    let report = collect_with_options(tmp.path(), &filter, &options);
+    assert!(report.errors.is_empty());
+    assert_eq!(report.stats.total_files, 1);
+    assert_eq!(report.stats.total_size, 4);
+
+    // Resolving the full paths fails once they get too long
+    let report = collect_with_options(tmp.path(), &filter, &WalkOptions::default());
+    assert!(!report.errors.is_empty());
+    assert_eq!(report.stats.total_files, 0);
+}
+
+// The number of file descriptors the test process has open
+#[cfg(target_os = "linux")]
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+// `count` directories in `root`, each holding a directory with a file in it
+#[cfg(target_os = "linux")]
+fn nested_pairs(root: &Path, count: usize) {
+    for i in 0..count {
+        let dir = root.join(format!("d{i}")).join("inner");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file.txt"), "x").unwrap();
+    }
+}
+
+// Records the most file descriptors open at any callback
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct FdCountingVisitor {
+    max_fds: usize,
+    files: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl FsVisitor for FdCountingVisitor {
+    fn visit_file(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.files += 1;
+        self.max_fds = self.max_fds.max(open_fds());
+        WalkControl::Continue
+    }
+
+    fn enter_dir(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+        self.max_fds = self.max_fds.max(open_fds());
+        WalkControl::Continue
+    }
+
+    fn on_error(&mut self, error: FsError) {
+        panic!("Unexpected error: {:?}", error);
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ParallelFsVisitor for FdCountingVisitor {
+    fn fork(&self) -> Self {
+        Self::default()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.max_fds = self.max_fds.max(other.max_fds);
+        self.files += other.files;
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn dir_fd_walks_dont_keep_queued_directories_open() {
+    let tmp = tempdir().unwrap();
+    nested_pairs(tmp.path(), 300);
+    let filter = TestPathFilter::default();
+    let before = open_fds();
+
+    for (strategy, threads) in [
+        (WalkStrategy::BreadthFirst, 1),
+        (WalkStrategy::DepthFirst, 4),
+    ] {
+        let options = WalkOptions {
+            dir_fds: true,
+            strategy,
+            threads,
+            ..Default::default()
+        };
+        let mut visitor = FdCountingVisitor::default();
+        walk_dir_parallel(tmp.path(), &mut visitor, &filter, &options);
+        assert_eq!(visitor.files, 300);
+        // Every queued `inner` directory used to keep its parent open. Leave some
+        // room for other tests running at the same time.
+        assert!(
+            visitor.max_fds < before + 100,
+            "{strategy:?}, {threads} threads: {} fds open, {before} before",
+            visitor.max_fds
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn dir_fd_walk_reports_directories_replaced_after_listing() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    for dir in ["a", "b"] {
+        std::fs::create_dir(root.join(dir)).unwrap();
+        std::fs::write(root.join(dir).join("file.txt"), "x").unwrap();
+    }
+    let filter = TestPathFilter::default();
+    let options = WalkOptions {
+        dir_fds: true,
+        strategy: WalkStrategy::BreadthFirst,
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let mut walker = Walker::new(root, &filter, &options);
+    let entry = walker.next().unwrap().unwrap();
+    assert_eq!(entry.event(), WalkEvent::EnterDir);
+    assert_eq!(entry.path(), root.join("a"));
+
+    // `b` was listed before it is swapped for another directory
+    std::fs::rename(root.join("b"), root.join("old")).unwrap();
+    std::fs::create_dir(root.join("b")).unwrap();
+
+    let rest: Vec<_> = walker.collect();
+    let errors: Vec<_> = rest
+        .iter()
+        .filter_map(|entry| entry.as_ref().err())
+        .collect();
+    assert_eq!(errors.len(), 1, "{rest:?}");
+    assert!(matches!(errors[0], FsError::Io(path, _) if *path == root.join("b")));
+    let files: Vec<_> = rest
+        .iter()
+        .flatten()
+        .filter(|entry| entry.event() == WalkEvent::File)
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    assert_eq!(files, vec![root.join("a/file.txt")]);
+}
+
+/*
+Visitor composition related tests
+*/
+#[test]
+fn tuple_of_visitors_shares_one_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let options = WalkOptions {
+            threads,
+            ..Default::default()
+        };
+        let finder = FindVisitor::new(r"^b", FindType::File).unwrap();
+        let mut both = (StatsVisitor::default(), finder);
+        let partial = walk_dir_parallel(&root, &mut both, &filter, &options);
+        let (stats, finder) = both;
+
+        let stats = stats.into_report(partial).stats;
+        let expected = collect_with_options(&root, &filter, &options).stats;
+        assert_eq!(stats.total_files, expected.total_files);
+        assert_eq!(stats.total_dirs, expected.total_dirs);
+        assert_eq!(stats.total_size, expected.total_size);
+
+        let mut found: Vec<_> = finder
+            .into_report(partial)
+            .entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![root.join("b/b1.txt"), root.join("b/b2/b3/b4.txt")]
+        );
+    }
+}
+
+#[test]
+fn boxed_visitors_skip_what_any_of_them_skips() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let mut visitors: Vec<Box<dyn FsVisitor>> = vec![
+        Box::new(ControlTestVisitor {
+            skip_dir: Some("a"),
+            ..Default::default()
+        }),
+        Box::new(WalkTestVisitor::default()),
+    ];
+    walk_dir_with_options(&root, &mut visitors, &TestPathFilter::default(), &options);
+
+    let mut both = (
+        ControlTestVisitor {
+            skip_dir: Some("a"),
+            ..Default::default()
+        },
+        WalkTestVisitor::default(),
+    );
+    walk_dir_with_options(&root, &mut both, &TestPathFilter::default(), &options);
+    let (control, plain) = both;
+
+    // Neither visitor sees the contents of `a`, both see it being exited
+    let files: Vec<_> = plain
+        .seen_files
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect();
+    assert_eq!(files, control.files);
+    assert!(!files.contains(&root.join("a/a1.txt")));
+    assert_eq!(files.len(), 4);
+    assert!(control.dirs_exit.contains(&root.join("a")));
+}
+
+#[test]
+fn stop_from_one_visitor_stops_all() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let mut visitors = vec![
+        ControlTestVisitor::default(),
+        ControlTestVisitor {
+            stop_at_file: Some("a1.txt"),
+            ..Default::default()
+        },
+    ];
+    walk_dir_with_options(&root, &mut visitors, &TestPathFilter::default(), &options);
+
+    for visitor in &visitors {
+        assert_eq!(visitor.files.last(), Some(&root.join("a/a1.txt")));
+        assert_eq!(visitor.files.len(), 1);
+    }
+}
+
+/*
+Visitor adapter related tests
+*/
+#[test]
+fn visitors_only_implement_the_callbacks_they_need() {
+    struct Files(usize);
+
+    impl FsVisitor for Files {
+        fn visit_file(&mut self, _path: &Path, _meta: &EntryMeta, _depth: usize) -> WalkControl {
+            self.0 += 1;
+            WalkControl::Continue
+        }
+    }
+
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let mut files = Files(0);
+    walk_dir(
+        &tmp.path().join("root"),
+        &mut files,
+        &TestPathFilter::default(),
+        None,
+        false,
+    );
+    assert_eq!(files.0, 6);
+}
+
+#[test]
+fn fn_visitor_sees_every_callback_and_steers_the_walk() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let mut events = Vec::new();
+    let mut visitor = FnVisitor::new(|visit| match visit {
+        Visit::EnterDir { path, depth, .. } => {
+            events.push(format!("enter {} {depth}", path.display()));
+            if path.ends_with("b") {
+                WalkControl::SkipSubtree
+            } else {
+                WalkControl::Continue
+            }
+        }
+        Visit::ExitDir { path, .. } => {
+            events.push(format!("exit {}", path.display()));
+            WalkControl::Continue
+        }
+        Visit::File { path, meta, .. } => {
+            events.push(format!("file {} {}", path.display(), meta.size().unwrap()));
+            WalkControl::Continue
+        }
+        _ => WalkControl::Continue,
+    });
+    walk_dir_with_options(&root, &mut visitor, &TestPathFilter::default(), &options);
+
+    let show = |path: &str| root.join(path).display().to_string();
+    assert_eq!(
+        events,
+        vec![
+            format!("enter {} 1", show("a")),
+            format!("file {} 1", show("a/a1.txt")),
+            format!("enter {} 2", show("a/a2")),
+            format!("file {} 1", show("a/a2/a3.txt")),
+            format!("exit {}", show("a/a2")),
+            format!("exit {}", show("a")),
+            format!("enter {} 1", show("b")),
+            format!("exit {}", show("b")),
+            format!("enter {} 1", show("c")),
+            format!("file {} 1", show("c/c1.txt")),
+            format!("exit {}", show("c")),
+            format!("file {} 5", show("file.txt")),
+        ]
+    );
+}
+
+#[test]
+fn fn_visitor_closures_may_return_nothing() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+
+    let mut dirs = 0;
+    let mut visitor = FnVisitor::new(|visit| {
+        if let Visit::EnterDir { .. } = visit {
+            dirs += 1;
+        }
+    });
+    walk_dir(
+        &tmp.path().join("root"),
+        &mut visitor,
+        &TestPathFilter::default(),
+        None,
+        false,
+    );
+    assert_eq!(dirs, 6);
+}
+
+#[test]
+fn counter_and_collector_agree_across_threads() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let filter = TestPathFilter::default();
+
+    for threads in [1, 4] {
+        let options = WalkOptions {
+            threads,
+            ..Default::default()
+        };
+        let mut counter = Counter::new(|visit| matches!(visit, Visit::File { .. }));
+        walk_dir_parallel(&root, &mut counter, &filter, &options);
+        assert_eq!(counter.count(), 6);
+
+        let mut collector = Collector::new(|visit| match visit {
+            Visit::File { path, depth, .. } if *depth > 1 => Some(path.to_path_buf()),
+            _ => None,
+        });
+        walk_dir_parallel(&root, &mut collector, &filter, &options);
+        let mut deep = collector.into_items();
+        deep.sort();
+        assert_eq!(
+            deep,
+            vec![
+                root.join("a/a1.txt"),
+                root.join("a/a2/a3.txt"),
+                root.join("b/b1.txt"),
+                root.join("b/b2/b3/b4.txt"),
+                root.join("c/c1.txt"),
+            ]
+        );
+    }
+}
+
+#[test]
+fn filtered_visitor_only_sees_what_passes() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        threads: 4,
+        ..Default::default()
+    };
+
+    // Leaving out `b` itself still walks its entries
+    let mut stats = StatsVisitor::default()
+        .filter(|visit| visit.path().is_some_and(|path| !path.ends_with("b")));
+    let partial = walk_dir_parallel(&root, &mut stats, &TestPathFilter::default(), &options);
+    let report = stats.into_inner().into_report(partial);
+    assert_eq!(report.stats.total_files, 6);
+    assert_eq!(report.stats.total_dirs, 5);
+}
+
+#[test]
+fn mapped_paths_include_the_paths_of_errors() {
+    let root = PathBuf::from("/walk/root");
+    let mut visitor = Collector::new(|visit| match visit {
+        Visit::Error(err) => Some((*err).clone()),
+        _ => None,
+    })
+    .map_paths(|path| path.strip_prefix(&root).unwrap_or(path).to_path_buf());
+
+    let io = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+    let pattern = |file: Option<&Path>| PatternError {
+        file: file.map(Path::to_path_buf),
+        line: 1,
+        pattern: "a[".to_string(),
+        reason: "unclosed character class".to_string(),
+    };
+    visitor.on_error(FsError::Io(root.join("a/b"), io()));
+    visitor.on_error(FsError::IgnoreFile(root.join("a/.gitignore"), io()));
+    visitor.on_error(FsError::InvalidPattern(pattern(Some(
+        &root.join(".ignore"),
+    ))));
+    visitor.on_error(FsError::InvalidPattern(pattern(None)));
+
+    let paths: Vec<Option<PathBuf>> = visitor
+        .into_inner()
+        .into_items()
+        .into_iter()
+        .map(|err| match err {
+            FsError::Io(path, _) | FsError::IgnoreFile(path, _) => Some(path),
+            FsError::InvalidPattern(err) => err.file,
+            FsError::RegexError(_) => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            Some(PathBuf::from("a/b")),
+            Some(PathBuf::from("a/.gitignore")),
+            Some(PathBuf::from(".ignore")),
+            None
+        ]
+    );
+}
+
+#[test]
+fn mapped_paths_reach_the_inner_visitor() {
+    let tmp = tempdir().unwrap();
+    create_fs_tree(tmp.path(), &wide_tree()).unwrap();
+    let root = tmp.path().join("root");
+    let options = WalkOptions {
+        sort: SortBy::Name,
+        ..Default::default()
+    };
+
+    let mut visitor = WalkTestVisitor::default()
+        .map_paths(|path| path.strip_prefix(&root).unwrap().to_path_buf());
+    walk_dir_with_options(&root, &mut visitor, &TestPathFilter::default(), &options);
+    let visitor = visitor.into_inner();
+
+    let files: Vec<_> = visitor
+        .seen_files
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    assert_eq!(
+        files,
+        [
+            "a/a1.txt",
+            "a/a2/a3.txt",
+            "b/b1.txt",
+            "b/b2/b3/b4.txt",
+            "c/c1.txt",
+            "file.txt"
+        ]
+        .map(PathBuf::from)
+    );
+}